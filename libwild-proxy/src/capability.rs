@@ -0,0 +1,340 @@
+//! Checks a link for features Wild is known not to support before linking, so it can go to the
+//! system linker right away instead of failing in libwild with a confusing error. Only the link
+//! line, the first bytes of each input and linker scripts are looked at.
+
+use crate::link_plan::{LinkInputKind, LinkPlan};
+use std::{
+    ffi::{OsStr, OsString},
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+/// Emulations libwild accepts with `-m`, and the `e_machine` they link.
+const EMULATIONS: &[(&str, u16)] = &[
+    ("elf_x86_64", object::elf::EM_X86_64),
+    ("elf_x86_64_sol2", object::elf::EM_X86_64),
+    ("aarch64elf", object::elf::EM_AARCH64),
+    ("aarch64linux", object::elf::EM_AARCH64),
+    ("elf64lriscv", object::elf::EM_RISCV),
+];
+
+/// Linker script keywords outside the subset libwild parses.
+const UNSUPPORTED_SCRIPT_KEYWORDS: &[&str] = &[
+    "MEMORY",
+    "PHDRS",
+    "INCLUDE",
+    "INSERT",
+    "OVERLAY",
+    "REGION_ALIAS",
+    "NOCROSSREFS",
+    "PROVIDE",
+    "PROVIDE_HIDDEN",
+    "HIDDEN",
+    "ASSERT",
+    "SEARCH_DIR",
+    "OUTPUT_ARCH",
+    "TARGET",
+    "STARTUP",
+    "EXTERN",
+    "AT",
+    "SUBALIGN",
+    "ONLY_IF_RO",
+    "ONLY_IF_RW",
+    "SORT",
+    "SORT_BY_NAME",
+    "SORT_BY_ALIGNMENT",
+    "SORT_BY_INIT_PRIORITY",
+    "BYTE",
+    "SHORT",
+    "LONG",
+    "QUAD",
+    "FILL",
+    "CONSTRUCTORS",
+];
+
+/// Scripts larger than this aren't read, they are unlikely to be anything Wild supports anyway.
+const MAX_SCRIPT_SIZE: u64 = 1 << 20;
+
+/// Returns why Wild can't link `plan`, if it's known not to.
+pub(crate) fn unsupported(plan: &LinkPlan) -> Option<String> {
+    let machine = match &plan.emulation {
+        Some(emulation) => {
+            let Some(&(_, machine)) = EMULATIONS
+                .iter()
+                .find(|(name, _)| OsStr::new(name) == emulation)
+            else {
+                return Some(format!(
+                    "emulation `{}` isn't supported",
+                    emulation.to_string_lossy()
+                ));
+            };
+            Some(machine)
+        }
+        None => None,
+    };
+    let flags = plan.flags().cloned().collect::<Vec<_>>();
+    if let Some(reason) = unsupported_flag(&flags) {
+        return Some(reason);
+    }
+
+    let inputs = plan.inputs().filter_map(|input| match &input.kind {
+        LinkInputKind::File(path) => Some(path),
+        LinkInputKind::Library(_) => None,
+    });
+    for path in inputs.chain(&scripts(&flags)) {
+        if let Some(reason) = unsupported_input(path, machine) {
+            return Some(format!("{}: {reason}", path.display()));
+        }
+    }
+    None
+}
+
+fn unsupported_flag(flags: &[OsString]) -> Option<String> {
+    let mut iter = flags.iter().filter_map(|flag| flag.to_str());
+    while let Some(flag) = iter.next() {
+        let (option, value) = match flag.split_once('=') {
+            Some((option, value)) => (option, Some(value)),
+            None => (flag, None),
+        };
+        let mut value = || value.or_else(|| iter.next()).unwrap_or_default();
+        match option {
+            "-r" | "-i" | "--relocatable" | "-relocatable" => {
+                return Some("relocatable output isn't supported".into());
+            }
+            "-b" | "--format" | "-format" => {
+                let format = value();
+                if !format.starts_with("elf") && format != "default" {
+                    return Some(format!("input format `{format}` isn't supported"));
+                }
+            }
+            "--oformat" | "-oformat" => {
+                let format = value();
+                if !format.starts_with("elf") {
+                    return Some(format!("output format `{format}` isn't supported"));
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Linker scripts given with `-T`.
+fn scripts(flags: &[OsString]) -> Vec<PathBuf> {
+    let mut scripts = Vec::new();
+    let mut iter = flags.iter();
+    while let Some(flag) = iter.next() {
+        if flag == "-T" || flag == "--script" {
+            scripts.extend(iter.next().map(PathBuf::from));
+        } else if let Some(script) = flag
+            .to_str()
+            .and_then(|flag| flag.strip_prefix("--script="))
+        {
+            scripts.push(script.into());
+        }
+    }
+    scripts
+}
+
+fn unsupported_input(path: &Path, machine: Option<u16>) -> Option<String> {
+    let mut header = [0; 20];
+    let mut file = std::fs::File::open(path).ok()?;
+    let read = file.read(&mut header).ok()?;
+    let header = &header[..read];
+    if header.starts_with(b"!<arch>\n") || header.starts_with(b"!<thin>\n") {
+        return None;
+    }
+    if header.starts_with(&object::elf::ELFMAG) {
+        return unsupported_elf(header, machine);
+    }
+    // Anything else is taken for a linker script, like glibc's `libc.so`.
+    if file.metadata().ok()?.len() > MAX_SCRIPT_SIZE {
+        return None;
+    }
+    let script = std::fs::read(path).ok()?;
+    // Not text, e.g. LLVM bitcode, libwild can tell what it is.
+    if script.contains(&0) {
+        return None;
+    }
+    unsupported_script(&script).map(|keyword| format!("linker script uses `{keyword}`"))
+}
+
+fn unsupported_elf(header: &[u8], machine: Option<u16>) -> Option<String> {
+    if header.len() < 20 {
+        return Some("truncated ELF header".into());
+    }
+    if header[4] != object::elf::ELFCLASS64 {
+        return Some("32-bit ELF isn't supported".into());
+    }
+    let bytes = [header[18], header[19]];
+    let input_machine = if header[5] == object::elf::ELFDATA2MSB {
+        u16::from_be_bytes(bytes)
+    } else {
+        u16::from_le_bytes(bytes)
+    };
+    if !EMULATIONS
+        .iter()
+        .any(|&(_, supported)| supported == input_machine)
+    {
+        return Some(format!("ELF machine {input_machine} isn't supported"));
+    }
+    match machine {
+        Some(machine) if machine != input_machine => Some(format!(
+            "ELF machine {input_machine} doesn't match the emulation"
+        )),
+        _ => None,
+    }
+}
+
+/// Returns the first keyword of `script` that Wild doesn't support.
+fn unsupported_script(script: &[u8]) -> Option<&'static str> {
+    let script = String::from_utf8_lossy(script);
+    let mut rest = script.as_ref();
+    let mut code = String::new();
+    while let Some(start) = rest.find("/*") {
+        code.push_str(&rest[..start]);
+        code.push(' ');
+        rest = rest[start..]
+            .find("*/")
+            .map_or("", |end| &rest[start + end + 2..]);
+    }
+    code.push_str(rest);
+    code.split(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .find_map(|token| {
+            UNSUPPORTED_SCRIPT_KEYWORDS
+                .iter()
+                .find(|keyword| **keyword == token)
+                .copied()
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use object::elf::{ELFCLASS32, ELFCLASS64, ELFDATA2LSB, ELFDATA2MSB};
+    use pretty_assertions::assert_eq;
+
+    fn plan(line: &str) -> LinkPlan {
+        LinkPlan::parse(&crate::shell::split(line.as_bytes()).unwrap()).unwrap()
+    }
+
+    fn elf_header(class: u8, data: u8, machine: u16) -> Vec<u8> {
+        let mut header = b"\x7fELF".to_vec();
+        header.extend([class, data, 1, 0]);
+        header.resize(16, 0);
+        header.extend(1u16.to_le_bytes());
+        header.extend(if data == object::elf::ELFDATA2MSB {
+            machine.to_be_bytes()
+        } else {
+            machine.to_le_bytes()
+        });
+        header
+    }
+
+    #[test]
+    fn emulations_and_flags() {
+        assert_eq!(None, unsupported(&plan("ld -m elf_x86_64 -o out")));
+        assert_eq!(
+            Some("relocatable output isn't supported".into()),
+            unsupported(&plan("ld -m elf_x86_64 -r -o out.o"))
+        );
+        assert_eq!(
+            Some("relocatable output isn't supported".into()),
+            unsupported(&plan("ld --relocatable -o out.o"))
+        );
+        assert_eq!(
+            Some("emulation `elf_i386` isn't supported".into()),
+            unsupported(&plan("ld -m elf_i386 -o out"))
+        );
+        assert_eq!(
+            Some("input format `binary` isn't supported".into()),
+            unsupported(&plan("ld -b binary -o out"))
+        );
+        assert_eq!(
+            Some("output format `binary` isn't supported".into()),
+            unsupported(&plan("ld --oformat=binary -o out"))
+        );
+        assert_eq!(None, unsupported(&plan("ld --oformat elf64-x86-64 -o out")));
+    }
+
+    #[test]
+    fn elf_headers() {
+        let x86_64 = Some(object::elf::EM_X86_64);
+        assert_eq!(
+            None,
+            unsupported_elf(
+                &elf_header(ELFCLASS64, ELFDATA2LSB, object::elf::EM_X86_64),
+                x86_64
+            )
+        );
+        assert_eq!(
+            Some("32-bit ELF isn't supported".into()),
+            unsupported_elf(
+                &elf_header(ELFCLASS32, ELFDATA2LSB, object::elf::EM_386),
+                None
+            )
+        );
+        assert_eq!(
+            Some("ELF machine 21 isn't supported".into()),
+            unsupported_elf(
+                &elf_header(ELFCLASS64, ELFDATA2MSB, object::elf::EM_PPC64),
+                None
+            )
+        );
+        assert_eq!(
+            Some("ELF machine 183 doesn't match the emulation".into()),
+            unsupported_elf(
+                &elf_header(ELFCLASS64, ELFDATA2LSB, object::elf::EM_AARCH64),
+                x86_64
+            )
+        );
+    }
+
+    #[test]
+    fn linker_scripts() {
+        assert_eq!(
+            None,
+            unsupported_script(
+                b"/* GNU ld script */\nOUTPUT_FORMAT(elf64-x86-64)\nGROUP ( /lib/x86_64-linux-gnu/libc.so.6 \
+                  /usr/lib/x86_64-linux-gnu/libc_nonshared.a  AS_NEEDED ( /lib64/ld-linux-x86-64.so.2 ) )"
+            )
+        );
+        assert_eq!(
+            Some("MEMORY"),
+            unsupported_script(b"MEMORY { rom (rx) : ORIGIN = 0, LENGTH = 256K }")
+        );
+        assert_eq!(
+            Some("PROVIDE"),
+            unsupported_script(b"/* PHDRS */ SECTIONS { .text : { *(.text) } PROVIDE(end = .); }")
+        );
+
+        let dir =
+            std::env::temp_dir().join(format!("wild-proxy-capability-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("main.o"),
+            elf_header(ELFCLASS64, ELFDATA2LSB, object::elf::EM_X86_64),
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("flash.ld"),
+            "MEMORY { flash : ORIGIN = 0, LENGTH = 1M }",
+        )
+        .unwrap();
+        let main = dir.join("main.o");
+        let script = dir.join("flash.ld");
+        assert_eq!(
+            None,
+            unsupported(&plan(&format!("ld -m elf_x86_64 {}", main.display())))
+        );
+        assert_eq!(
+            Some(format!("{}: linker script uses `MEMORY`", script.display())),
+            unsupported(&plan(&format!(
+                "ld -m elf_x86_64 -T {} {}",
+                script.display(),
+                main.display()
+            )))
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
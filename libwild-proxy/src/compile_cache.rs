@@ -0,0 +1,429 @@
+//! Opt-in cache of compile and assemble steps, when `WILD_PROXY_COMPILE_CACHE` names a directory.
+//! Like ccache in preprocessor mode, C-family compiles by GCC's `cc1*` or `clang -cc1` are keyed by
+//! their arguments and their input after preprocessing, `as` and `clang -cc1as` by their arguments
+//! and the contents of their inputs. On a hit, the step's `-o` output and dependency file are
+//! restored instead of running it. The least recently used entries are evicted once the directory
+//! grows past `WILD_PROXY_COMPILE_CACHE_SIZE`.
+
+use crate::output_cache::{OutputCache, add};
+use std::{
+    ffi::{OsStr, OsString},
+    io::{IsTerminal, Write},
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus, Stdio},
+};
+
+pub(crate) const CACHE_DIR_ENV: &str = "WILD_PROXY_COMPILE_CACHE";
+pub(crate) const CACHE_SIZE_ENV: &str = "WILD_PROXY_COMPILE_CACHE_SIZE";
+
+/// GCC front ends whose inputs are all visible after preprocessing. Others, like `f951` or `gnat1`,
+/// read module interfaces as well and aren't cached.
+const GCC_C_FRONT_ENDS: &[&str] = &["cc1", "cc1plus", "cc1obj", "cc1objplus"];
+
+/// Options that make a step write files besides its output, or read files that preprocessing
+/// doesn't show. Steps using them aren't cached.
+const UNCACHEABLE_OPTIONS: &[&str] = &[
+    "-fstack-usage",
+    "-fdump-",
+    "-fcallgraph-info",
+    "-fprofile-arcs",
+    "-fprofile-generate",
+    "-fprofile-use",
+    "-fprofile-instrument-use-path",
+    "-fprofile-sample-use",
+    "-fauto-profile",
+    "-ftest-coverage",
+    "-fopt-info",
+    "-save-temps",
+    "-gsplit-dwarf",
+    "-aux-info",
+    "-fplugin",
+    "-fmodule",
+    "-fprebuilt-module-path",
+    "-include-pch",
+    "-fsanitize-ignorelist",
+    "-fsanitize-system-ignorelist",
+    "-coverage-notes-file",
+    "-split-dwarf-output",
+    "-stack-usage-file",
+    "-opt-record-file",
+    "-ftime-trace",
+];
+
+/// Options naming the dependency file a compile writes, e.g. `-MD hello.d` passed to `cc1`. The
+/// file is cached along with the output. Like for GCC, the last one wins and `-MF` only names it.
+const DEPENDENCY_FILE_OPTIONS: &[&str] = &["-MD", "-MMD", "-MF", "-dependency-file"];
+
+/// What a step does, which decides how it's keyed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    /// `as` or `clang -cc1as`, keyed by the contents of their inputs.
+    Assemble,
+    /// A GCC C-family front end.
+    GccCompile,
+    /// `clang -cc1`.
+    ClangCompile,
+}
+
+impl Kind {
+    fn of(name: &[u8], args: &[OsString]) -> Option<Self> {
+        match args.first() {
+            Some(first) if first == "-cc1as" => Some(Self::Assemble),
+            Some(first) if first == "-cc1" => Some(Self::ClangCompile),
+            _ if name == b"as" => Some(Self::Assemble),
+            _ if GCC_C_FRONT_ENDS
+                .iter()
+                .any(|front_end| name == front_end.as_bytes()) =>
+            {
+                Some(Self::GccCompile)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A step that can be cached, with its key.
+pub(crate) struct CachedStep<'a> {
+    cache: &'a OutputCache,
+    key: String,
+    output: PathBuf,
+    dependency_file: Option<PathBuf>,
+    kind: Kind,
+}
+
+impl<'a> CachedStep<'a> {
+    /// Returns the step running `args` if it can be cached.
+    pub(crate) fn new(
+        cache: &'a OutputCache,
+        args: &[OsString],
+        env: &[(&str, &[u8])],
+    ) -> Option<Self> {
+        let (program, args) = args.split_first()?;
+        let kind = Kind::of(Path::new(program).file_name()?.as_bytes(), args)?;
+        let output = args
+            .windows(2)
+            .find_map(|window| (window[0] == "-o").then(|| PathBuf::from(&window[1])))?;
+        if args.iter().any(|arg| {
+            UNCACHEABLE_OPTIONS
+                .iter()
+                .any(|option| arg.as_bytes().starts_with(option.as_bytes()))
+                // Listings of `as`, e.g. `-alh=file`.
+                || (kind == Kind::Assemble && arg.as_bytes().starts_with(b"-a"))
+                || arg == "-E"
+        }) {
+            return None;
+        }
+        let (_, dependency_file) = split_dependency_file(args);
+        // Preprocessed input, e.g. from a `.i` file, is complete on its own.
+        let preprocess = kind != Kind::Assemble && !args.iter().any(|arg| arg == "-fpreprocessed");
+
+        let mut hasher = blake3::Hasher::new();
+        add(
+            &mut hasher,
+            concat!("wild-proxy ", env!("CARGO_PKG_VERSION")).as_bytes(),
+        );
+        // The compiler or assembler itself, changing when it's upgraded.
+        let program_path = resolve(program)?;
+        let metadata = program_path.metadata().ok()?;
+        add(&mut hasher, program_path.as_os_str().as_bytes());
+        add(&mut hasher, &metadata.len().to_le_bytes());
+        add(&mut hasher, &metadata.mtime().to_le_bytes());
+        add(&mut hasher, &metadata.mtime_nsec().to_le_bytes());
+
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            // GCC writes to a new temporary file each time.
+            if arg == "-o" {
+                add(&mut hasher, b"-o");
+                iter.next();
+                continue;
+            }
+            // Where the dependency file goes is part of the key, not what it contained before.
+            if DEPENDENCY_FILE_OPTIONS.iter().any(|option| arg == option) {
+                add(&mut hasher, arg.as_bytes());
+                add(&mut hasher, iter.next()?.as_bytes());
+                continue;
+            }
+            // Assembly from the previous step is in a temporary file too, so inputs are keyed by
+            // their contents.
+            let path = Path::new(arg);
+            if !preprocess && path.is_file() {
+                add(&mut hasher, &std::fs::read(path).ok()?);
+            } else {
+                add(&mut hasher, arg.as_bytes());
+            }
+        }
+        if preprocess {
+            add(&mut hasher, &preprocessed(program, args, env)?);
+        }
+        Some(Self {
+            cache,
+            key: hasher.finalize().to_hex().to_string(),
+            output,
+            dependency_file,
+            kind,
+        })
+    }
+
+    /// The files the step writes, with the keys they're cached under.
+    fn entries(&self) -> impl Iterator<Item = (String, &Path)> {
+        std::iter::once((self.key.clone(), self.output.as_path())).chain(
+            self.dependency_file
+                .as_deref()
+                .map(|file| (format!("{}-d", self.key), file)),
+        )
+    }
+
+    /// Restores the output of the step. Returns whether it was cached.
+    pub(crate) fn restore(&self) -> bool {
+        self.entries()
+            .all(|(key, file)| self.cache.restore(&key, file))
+    }
+
+    /// Runs the step and caches its output if it succeeded without diagnostics, which would be
+    /// lost on a hit.
+    pub(crate) fn run(
+        &self,
+        args: &[OsString],
+        env: &[(&str, &[u8])],
+    ) -> std::io::Result<ExitStatus> {
+        let mut command = Command::new(&args[0]);
+        command.args(&args[1..]).envs(crate::env_vars(env));
+        // Diagnostics go through a pipe now, but should stay as colourful as they were. Clang's
+        // driver already decided for `-cc1`.
+        if self.kind == Kind::GccCompile
+            && std::io::stderr().is_terminal()
+            && std::env::var_os("GCC_COLORS").is_none_or(|colors| !colors.is_empty())
+            && !args
+                .iter()
+                .any(|arg| arg.as_bytes().starts_with(b"-fdiagnostics-color"))
+            && !args.iter().any(|arg| arg == "-fno-diagnostics-color")
+        {
+            command.arg("-fdiagnostics-color=always");
+        }
+        let output = command.stderr(Stdio::piped()).output()?;
+        std::io::stderr().write_all(&output.stderr)?;
+        if output.status.success() && output.stderr.is_empty() {
+            for (key, file) in self.entries() {
+                self.cache.store(&key, file);
+            }
+        }
+        Ok(output.status)
+    }
+}
+
+/// Returns `args` without dependency file options, and the dependency file the step writes.
+fn split_dependency_file(args: &[OsString]) -> (Vec<OsString>, Option<PathBuf>) {
+    let mut rest = Vec::with_capacity(args.len());
+    let mut file = None;
+    let mut writes_file = false;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if let Some(option) = DEPENDENCY_FILE_OPTIONS
+            .iter()
+            .find(|option| arg == **option)
+        {
+            writes_file |= *option != "-MF";
+            file = iter.next().map(PathBuf::from);
+        } else if let Some(joined) = arg.as_bytes().strip_prefix(b"-MF")
+            && !joined.is_empty()
+        {
+            file = Some(PathBuf::from(OsStr::from_bytes(joined)));
+        } else {
+            rest.push(arg.clone());
+        }
+    }
+    (rest, file.filter(|_| writes_file))
+}
+
+/// The input of a compile after preprocessing, or `None` if preprocessing fails. The step runs
+/// anyway then and reports why.
+fn preprocessed(program: &OsString, args: &[OsString], env: &[(&str, &[u8])]) -> Option<Vec<u8>> {
+    let (args, _) = split_dependency_file(args);
+    let mut preprocess_args = Vec::with_capacity(args.len() + 1);
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        // GCC refuses the other dependency options without one writing a dependency file.
+        if arg == "-o" || arg == "-MQ" || arg == "-MT" {
+            iter.next();
+        } else if arg != "-MP" && arg != "-MG" {
+            preprocess_args.push(arg);
+        }
+    }
+    // The last action wins for `clang -cc1`.
+    preprocess_args.push("-E".into());
+    let output = Command::new(program)
+        .args(&preprocess_args)
+        .envs(crate::env_vars(env))
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    output.status.success().then_some(output.stdout)
+}
+
+/// Finds `program` like the compiler driver runs it.
+fn resolve(program: &OsString) -> Option<PathBuf> {
+    if crate::has_dir(program) {
+        return Some(program.into());
+    }
+    let paths = std::env::var_os("PATH")?;
+    std::env::split_paths(&paths)
+        .map(|dir| dir.join(program))
+        .find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::os::unix::fs::PermissionsExt;
+
+    /// Stands in for a compiler. Preprocessing prints `header.h` next to the input and the input,
+    /// compiling writes the same to `-o` and a dependency file for `-MD`.
+    const FAKE_COMPILER: &str = r#"#!/bin/sh
+while [ $# -gt 0 ]; do
+    case $1 in
+        -o) out=$2; shift ;;
+        -MD) deps=$2; shift ;;
+        -MQ) shift ;;
+        -E) preprocess=1 ;;
+        -*) ;;
+        *) input=$1 ;;
+    esac
+    shift
+done
+header=$(dirname "$input")/header.h
+if [ -n "$preprocess" ]; then
+    cat "$header" "$input"
+    exit
+fi
+cat "$header" "$input" > "$out"
+[ -z "$deps" ] || echo "hello.o: $input $header" > "$deps"
+"#;
+
+    fn program(dir: &Path, name: &str, contents: &str) -> PathBuf {
+        let path = dir.join(name);
+        std::fs::write(&path, contents).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    #[test]
+    fn assemble_keys() {
+        let dir =
+            std::env::temp_dir().join(format!("wild-proxy-compile-cache-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cache = OutputCache::new(dir.join("cache"), 1 << 20);
+        let program = program(&dir, "as", "");
+        let key = |line: &str| {
+            let args =
+                crate::shell::split(format!("{} {line}", program.display()).as_bytes()).unwrap();
+            CachedStep::new(&cache, &args, &[]).map(|step| step.key)
+        };
+        let input = |name: &str, contents: &str| {
+            let path = dir.join(name);
+            std::fs::write(&path, contents).unwrap();
+            path.display().to_string()
+        };
+
+        let first = key(&format!("--64 -o a.o {}", input("cc1.s", "nop"))).unwrap();
+        // Temporary names of the assembly and where it goes don't matter.
+        assert_eq!(
+            Some(&first),
+            key(&format!("--64 -o b.o {}", input("cc2.s", "nop"))).as_ref()
+        );
+        assert_ne!(
+            Some(&first),
+            key(&format!("--64 -o a.o {}", input("cc1.s", "ret"))).as_ref()
+        );
+        assert_ne!(
+            Some(&first),
+            key(&format!("--32 -o a.o {}", input("cc1.s", "nop"))).as_ref()
+        );
+        assert_eq!(None, key(&format!("--64 {}", input("cc1.s", "nop"))));
+        assert_eq!(
+            None,
+            key(&format!("-fstack-usage -o a.o {}", input("cc1.s", "nop")))
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn compile_steps() {
+        let dir =
+            std::env::temp_dir().join(format!("wild-proxy-compile-steps-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cache = OutputCache::new(dir.join("cache"), 1 << 20);
+        let cc1 = program(&dir, "cc1", FAKE_COMPILER);
+        let clang = program(&dir, "clang", FAKE_COMPILER);
+        std::fs::write(dir.join("header.h"), "int answer = 42;\n").unwrap();
+        std::fs::write(dir.join("hello.c"), "#include \"header.h\"\n").unwrap();
+        let args = |program: &Path, line: &str| {
+            crate::shell::split(format!("{} {line}", program.display()).as_bytes()).unwrap()
+        };
+        let cc1_args = |output: &str| {
+            args(
+                &cc1,
+                &format!(
+                    "-quiet {} -MD {} -MQ hello.o -o {}",
+                    dir.join("hello.c").display(),
+                    dir.join("hello.d").display(),
+                    dir.join(output).display()
+                ),
+            )
+        };
+        let read = |name: &str| std::fs::read_to_string(dir.join(name)).unwrap();
+
+        let step = CachedStep::new(&cache, &cc1_args("cc1.s"), &[]).unwrap();
+        assert!(!step.restore());
+        assert!(step.run(&cc1_args("cc1.s"), &[]).unwrap().success());
+        let compiled = read("cc1.s");
+        let dependencies = read("hello.d");
+
+        // Hits with a new temporary output, restoring the dependency file as well.
+        std::fs::remove_file(dir.join("hello.d")).unwrap();
+        let step = CachedStep::new(&cache, &cc1_args("cc2.s"), &[]).unwrap();
+        assert!(step.restore());
+        assert_eq!(compiled, read("cc2.s"));
+        assert_eq!(dependencies, read("hello.d"));
+
+        // Headers are part of the key.
+        std::fs::write(dir.join("header.h"), "int answer = 43;\n").unwrap();
+        let step = CachedStep::new(&cache, &cc1_args("cc3.s"), &[]).unwrap();
+        assert!(!step.restore());
+
+        // So they are for `clang -cc1`, which is keyed by preprocessing unlike `clang -cc1as`.
+        let clang_args = |kind: &str| {
+            args(
+                &clang,
+                &format!(
+                    "{kind} -emit-obj -o hello.o {}",
+                    dir.join("hello.c").display()
+                ),
+            )
+        };
+        let compile_key = || {
+            CachedStep::new(&cache, &clang_args("-cc1"), &[])
+                .unwrap()
+                .key
+        };
+        let assemble_key = || {
+            CachedStep::new(&cache, &clang_args("-cc1as"), &[])
+                .unwrap()
+                .key
+        };
+        let (compiled, assembled) = (compile_key(), assemble_key());
+        std::fs::write(dir.join("header.h"), "int answer = 44;\n").unwrap();
+        assert_ne!(compiled, compile_key());
+        assert_eq!(assembled, assemble_key());
+
+        // Front ends reading module interfaces aren't cached.
+        let f951 = program(&dir, "f951", FAKE_COMPILER);
+        assert!(CachedStep::new(&cache, &args(&f951, "-o hello.s hello.f90"), &[]).is_none());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,309 @@
+//! Opt-in recorder of intercepted invocations. When `WILD_PROXY_COMPILE_DB` points at a directory,
+//! every compile is merged into `compile_commands.json` there (as understood by clangd and friends)
+//! and every successful link into `link_commands.json`, together with the linker that produced the
+//! output and its arguments: `wild` and the arguments passed to libwild, or the system linker.
+
+use anyhow::{Context, Result};
+use serde_json::{Value, json};
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+const COMPILE_DB_ENV: &str = "WILD_PROXY_COMPILE_DB";
+const COMPILE_COMMANDS: &str = "compile_commands.json";
+const LINK_COMMANDS: &str = "link_commands.json";
+
+/// Driver options whose value is passed as the next argument.
+pub(crate) const OPTIONS_WITH_VALUE: &[&str] = &[
+    "-o",
+    "-x",
+    "-I",
+    "-D",
+    "-U",
+    "-include",
+    "-imacros",
+    "-isystem",
+    "-iquote",
+    "-idirafter",
+    "-iprefix",
+    "-isysroot",
+    "--sysroot",
+    "-MF",
+    "-MT",
+    "-MQ",
+    "-L",
+    "-T",
+    "-u",
+    "-e",
+    "-Xlinker",
+    "-Xassembler",
+    "-Xpreprocessor",
+    "-Xclang",
+    "--param",
+    "-aux-info",
+    "-target",
+    "-arch",
+];
+
+const SOURCE_EXTENSIONS: &[&str] = &[
+    "c", "i", "cc", "cp", "cxx", "cpp", "CPP", "c++", "C", "ii", "m", "mi", "mm", "M", "mii", "s",
+    "S", "sx",
+];
+
+fn database_dir() -> Option<PathBuf> {
+    std::env::var_os(COMPILE_DB_ENV)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Records one entry per source file compiled by this driver invocation.
+pub(crate) fn record_compile(compiler: &Path, args: &[String]) {
+    let Some(dir) = database_dir() else {
+        return;
+    };
+    if let Err(e) = record_compile_in(&dir, compiler, args) {
+        tracing::warn!("Failed to update {COMPILE_COMMANDS}: {e:?}");
+    }
+}
+
+/// Records the linker that produced the output of this link and its arguments.
+pub(crate) fn record_link(linker: &str, linker_args: &[String]) {
+    let Some(dir) = database_dir() else {
+        return;
+    };
+    if let Err(e) = record_link_in(&dir, linker, linker_args) {
+        tracing::warn!("Failed to update {LINK_COMMANDS}: {e:?}");
+    }
+}
+
+fn record_compile_in(dir: &Path, compiler: &Path, args: &[String]) -> Result<()> {
+    let directory = std::env::current_dir().context("Could not get current directory")?;
+    let entries = compile_entries(&directory, compiler, args);
+    if entries.is_empty() {
+        return Ok(());
+    }
+    merge_into(&dir.join(COMPILE_COMMANDS), entries, &["directory", "file"])
+}
+
+fn record_link_in(dir: &Path, linker: &str, linker_args: &[String]) -> Result<()> {
+    let directory = std::env::current_dir().context("Could not get current directory")?;
+    let entry = link_entry(&directory, linker, linker_args);
+    merge_into(
+        &dir.join(LINK_COMMANDS),
+        vec![entry],
+        &["directory", "output"],
+    )
+}
+
+fn compile_entries(directory: &Path, compiler: &Path, args: &[String]) -> Vec<Value> {
+    if args
+        .iter()
+        .any(|arg| ["-E", "-M", "-MM"].contains(&arg.as_str()))
+    {
+        return Vec::new();
+    }
+
+    let mut sources = Vec::new();
+    let mut output = None;
+    let mut iter = args.iter().enumerate();
+    while let Some((index, arg)) = iter.next() {
+        if OPTIONS_WITH_VALUE.contains(&arg.as_str()) {
+            if let Some((_, value)) = iter.next()
+                && arg == "-o"
+            {
+                output = Some(value);
+            }
+        } else if !arg.starts_with('-') && is_source(arg) {
+            sources.push(index);
+        }
+    }
+    let is_compile_only = args.iter().any(|arg| arg == "-c" || arg == "-S");
+
+    sources
+        .iter()
+        .map(|&source_index| {
+            let arguments = std::iter::once(compiler.to_string_lossy().into_owned())
+                .chain(
+                    args.iter()
+                        .enumerate()
+                        .filter(|(index, _)| *index == source_index || !sources.contains(index))
+                        .map(|(_, arg)| arg.clone()),
+                )
+                .collect::<Vec<_>>();
+            let mut entry = json!({
+                "directory": directory.to_string_lossy(),
+                "arguments": arguments,
+                "file": args[source_index],
+            });
+            if let Some(output) = output.filter(|_| is_compile_only && sources.len() == 1) {
+                entry["output"] = json!(output);
+            }
+            entry
+        })
+        .collect()
+}
+
+fn link_entry(directory: &Path, linker: &str, linker_args: &[String]) -> Value {
+    let output = linker_args
+        .windows(2)
+        .find_map(|window| (window[0] == "-o").then(|| window[1].clone()))
+        .unwrap_or_else(|| "a.out".to_owned());
+    json!({
+        "directory": directory.to_string_lossy(),
+        "output": output,
+        "linker": linker,
+        "arguments": linker_args,
+    })
+}
+
+fn is_source(arg: &str) -> bool {
+    Path::new(arg)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| SOURCE_EXTENSIONS.contains(&ext))
+}
+
+/// Merges `new_entries` into the JSON array stored at `path`. Entries with the same values of
+/// `key_fields` are replaced. The directory is locked for the whole read-modify-write cycle, since
+/// parallel builds run many compilers at once. Not the file, which is replaced to update it.
+fn merge_into(path: &Path, new_entries: Vec<Value>, key_fields: &[&str]) -> Result<()> {
+    let dir = path.parent().context("Database has no directory")?;
+    let lock = File::open(dir).with_context(|| format!("Failed to open `{}`", dir.display()))?;
+    lock.lock()
+        .with_context(|| format!("Failed to lock `{}`", dir.display()))?;
+
+    let existing = match std::fs::read_to_string(path) {
+        Ok(contents) if !contents.trim().is_empty() => serde_json::from_str::<Value>(&contents)
+            .with_context(|| format!("Failed to parse `{}`", path.display()))?
+            .as_array()
+            .cloned()
+            .unwrap_or_default(),
+        Ok(_) => Vec::new(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read `{}`", path.display())),
+    };
+    let merged = merge_entries(existing, new_entries, key_fields);
+
+    let temp_path = path.with_extension(format!("json.{}", std::process::id()));
+    std::fs::write(&temp_path, serde_json::to_string_pretty(&merged)? + "\n")
+        .with_context(|| format!("Failed to write `{}`", temp_path.display()))?;
+    std::fs::rename(&temp_path, path)
+        .with_context(|| format!("Failed to replace `{}`", path.display()))?;
+    Ok(())
+}
+
+fn merge_entries(
+    mut existing: Vec<Value>,
+    new_entries: Vec<Value>,
+    key_fields: &[&str],
+) -> Vec<Value> {
+    let key = |entry: &Value| {
+        key_fields
+            .iter()
+            .map(|field| entry.get(field).cloned())
+            .collect::<Vec<_>>()
+    };
+    for entry in new_entries {
+        let entry_key = key(&entry);
+        if let Some(slot) = existing.iter_mut().find(|old| key(old) == entry_key) {
+            *slot = entry;
+        } else {
+            existing.push(entry);
+        }
+    }
+    existing
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn strings(args: &[&str]) -> Vec<String> {
+        args.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn compile_entry_per_source() {
+        let args = strings(&[
+            "-O2", "-I", "inc.c", "a.c", "b.cpp", "-o", "out.c", "main.o",
+        ]);
+        let entries = compile_entries(Path::new("/build"), Path::new("/usr/bin/gcc"), &args);
+        assert_eq!(2, entries.len());
+        assert_eq!(Some("a.c"), entries[0].get("file").and_then(Value::as_str));
+        assert_eq!(
+            vec![
+                "/usr/bin/gcc",
+                "-O2",
+                "-I",
+                "inc.c",
+                "a.c",
+                "-o",
+                "out.c",
+                "main.o"
+            ],
+            entries[0]
+                .get("arguments")
+                .and_then(Value::as_array)
+                .unwrap()
+                .iter()
+                .filter_map(Value::as_str)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(None, entries[0].get("output"));
+        assert_eq!(
+            Some("b.cpp"),
+            entries[1].get("file").and_then(Value::as_str)
+        );
+    }
+
+    #[test]
+    fn compile_only_entry_has_output() {
+        let args = strings(&["-c", "a.c", "-o", "a.o"]);
+        let entries = compile_entries(Path::new("/build"), Path::new("cc"), &args);
+        assert_eq!(
+            Some("a.o"),
+            entries[0].get("output").and_then(Value::as_str)
+        );
+    }
+
+    #[test]
+    fn preprocess_is_not_recorded() {
+        let args = strings(&["-E", "a.c"]);
+        assert!(compile_entries(Path::new("/build"), Path::new("cc"), &args).is_empty());
+    }
+
+    #[test]
+    fn link_entry_names_linker() {
+        let args = strings(&["-pie", "-o", "hello", "hello.o"]);
+        assert_eq!(
+            json!({
+                "directory": "/build",
+                "output": "hello",
+                "linker": "wild",
+                "arguments": ["-pie", "-o", "hello", "hello.o"],
+            }),
+            link_entry(Path::new("/build"), "wild", &args)
+        );
+    }
+
+    #[test]
+    fn merge_replaces_same_key() {
+        let entry =
+            |file: &str, arg: &str| json!({ "directory": "/b", "file": file, "arguments": arg });
+        let merged = merge_entries(
+            vec![entry("a.c", "old"), entry("b.c", "old")],
+            vec![entry("a.c", "new"), entry("c.c", "new")],
+            &["directory", "file"],
+        );
+        assert_eq!(
+            vec![
+                entry("a.c", "new"),
+                entry("b.c", "old"),
+                entry("c.c", "new")
+            ],
+            merged
+        );
+    }
+}
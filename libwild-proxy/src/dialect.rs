@@ -0,0 +1,267 @@
+//! Flags only GNU ld, gold or lld understand, translated to what Wild does. Toolchains configured
+//! for one of them, e.g. clang with `-fuse-ld=lld` as the default, put these in every link command.
+
+use std::{ffi::OsString, path::Path};
+
+/// The linker a link command was written for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Dialect {
+    Bfd,
+    Gold,
+    Lld,
+}
+
+impl Dialect {
+    /// Tells the dialect by the name of the linker in the link command. GCC runs `collect2`, which
+    /// runs GNU ld unless `-fuse-ld` says otherwise, and that never reaches the compiler.
+    pub(crate) fn detect(linker: &Path) -> Self {
+        let name = linker
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_lowercase();
+        if name.contains("lld") {
+            Self::Lld
+        } else if name.contains("gold") {
+            Self::Gold
+        } else {
+            Self::Bfd
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Translation {
+    /// Replace with Wild's equivalent.
+    Replace(&'static [&'static str]),
+    /// Drop without a word, the flag only changes the diagnostics or memory use of the linker.
+    Ignore,
+    /// Drop with a warning, Wild doesn't do it (yet).
+    Unsupported,
+}
+
+/// Where the value of a matched option is.
+enum Value {
+    None,
+    Inline,
+    Next,
+}
+
+struct Entry {
+    dialects: &'static [Dialect],
+    /// The option with two dashes, `-z` keywords as `-z <keyword>`. A trailing `=` matches any value,
+    /// given either after `=` or as the next argument.
+    option: &'static str,
+    translation: Translation,
+}
+
+const fn entry(
+    dialects: &'static [Dialect],
+    option: &'static str,
+    translation: Translation,
+) -> Entry {
+    Entry {
+        dialects,
+        option,
+        translation,
+    }
+}
+
+use Dialect::{Bfd, Gold, Lld};
+use Translation::{Ignore, Replace, Unsupported};
+
+/// Only flags libwild doesn't parse, the first matching entry wins. The ones it parses, like gold's
+/// `--icf=` or `--thread-count`, are left to it.
+const TABLE: &[Entry] = &[
+    // lld
+    entry(
+        &[Lld],
+        "--pack-dyn-relocs=relr",
+        Replace(&["-z", "pack-relative-relocs"]),
+    ),
+    entry(
+        &[Lld],
+        "--pack-dyn-relocs=android+relr",
+        Replace(&["-z", "pack-relative-relocs"]),
+    ),
+    entry(&[Lld], "--pack-dyn-relocs=none", Replace(&[])),
+    entry(&[Lld], "--pack-dyn-relocs=", Unsupported),
+    entry(&[Lld], "--use-android-relr-tags", Unsupported),
+    entry(&[Lld], "-z rel", Unsupported),
+    entry(&[Lld], "--rosegment", Unsupported),
+    entry(&[Lld], "--no-rosegment", Unsupported),
+    entry(&[Lld], "--apply-dynamic-relocs", Unsupported),
+    entry(&[Lld, Gold], "--symbol-ordering-file=", Unsupported),
+    entry(&[Lld], "--call-graph-ordering-file=", Unsupported),
+    entry(&[Lld], "--lto-O0", Ignore),
+    entry(&[Lld], "--lto-O1", Ignore),
+    entry(&[Lld], "--lto-O2", Ignore),
+    entry(&[Lld], "--lto-O3", Ignore),
+    entry(&[Lld], "--lto-partitions=", Ignore),
+    entry(&[Lld], "--thinlto-cache-dir=", Ignore),
+    entry(&[Lld], "--thinlto-cache-policy=", Ignore),
+    entry(&[Lld], "--thinlto-jobs=", Ignore),
+    entry(&[Lld], "--warn-backrefs", Ignore),
+    entry(&[Lld], "--error-limit=", Ignore),
+    entry(&[Lld], "--time-trace", Ignore),
+    // gold
+    entry(&[Gold], "--icf-iterations=", Ignore),
+    entry(&[Gold], "--keep-unique=", Ignore),
+    entry(&[Gold], "--section-ordering-file=", Unsupported),
+    entry(&[Gold], "--no-ctors-in-init-array", Unsupported),
+    entry(&[Gold], "--detect-odr-violations", Ignore),
+    entry(&[Gold], "--incremental", Unsupported),
+    entry(&[Gold], "--no-incremental", Ignore),
+    // GNU ld
+    entry(&[Bfd], "--no-keep-memory", Ignore),
+    entry(&[Bfd], "--reduce-memory-overheads", Ignore),
+    entry(&[Bfd], "--warn-rwx-segments", Ignore),
+    entry(&[Bfd], "--no-warn-rwx-segments", Ignore),
+    entry(&[Bfd], "--check-sections", Ignore),
+    entry(&[Bfd], "--no-check-sections", Ignore),
+    entry(&[Bfd, Gold, Lld], "--print-gc-sections", Ignore),
+    entry(&[Bfd], "--package-metadata=", Unsupported),
+    entry(
+        &[Bfd, Gold, Lld],
+        "--compress-debug-sections=none",
+        Replace(&[]),
+    ),
+    entry(&[Bfd, Gold, Lld], "--compress-debug-sections=", Unsupported),
+    entry(&[Bfd], "--sort-section=", Unsupported),
+    entry(&[Bfd], "--discard-none", Unsupported),
+    entry(&[Bfd], "--pic-veneer", Unsupported),
+];
+
+/// Translates the flags of `dialect` that Wild would reject and warns about the ones that are
+/// dropped.
+pub(crate) fn translate(args: Vec<OsString>, dialect: Dialect) -> Vec<OsString> {
+    let mut translated = Vec::with_capacity(args.len());
+    let mut iter = args.into_iter().peekable();
+    while let Some(arg) = iter.next() {
+        let Some(text) = arg.to_str() else {
+            translated.push(arg);
+            continue;
+        };
+        // `-z keyword` and `-zkeyword`, lld and gold also take long options with a single dash.
+        let (option, consumed) = match text.strip_prefix("-z") {
+            Some("") => match iter.peek().and_then(|next| next.to_str()) {
+                Some(keyword) => (format!("-z {keyword}"), 2),
+                None => (text.to_owned(), 1),
+            },
+            Some(keyword) => (format!("-z {keyword}"), 1),
+            None if text.starts_with('-') && !text.starts_with("--") && text.len() > 2 => {
+                (format!("-{text}"), 1)
+            }
+            None => (text.to_owned(), 1),
+        };
+
+        let Some((entry, value)) = TABLE
+            .iter()
+            .filter(|entry| entry.dialects.contains(&dialect))
+            .find_map(|entry| {
+                let Some(name) = entry.option.strip_suffix('=') else {
+                    return (option == entry.option).then_some((entry, Value::None));
+                };
+                if option.starts_with(entry.option) {
+                    Some((entry, Value::Inline))
+                } else {
+                    (option == name && consumed == 1).then_some((entry, Value::Next))
+                }
+            })
+        else {
+            translated.push(arg);
+            continue;
+        };
+        if consumed == 2 {
+            iter.next();
+        }
+        let shown = match value {
+            Value::None | Value::Inline => option,
+            Value::Next => {
+                let value = iter.next().unwrap_or_default();
+                format!("{option} {}", value.to_string_lossy())
+            }
+        };
+        match entry.translation {
+            Replace(replacement) => {
+                tracing::debug!("Replaced {dialect:?} flag `{shown}` with {replacement:?}");
+                translated.extend(replacement.iter().map(OsString::from));
+            }
+            Ignore => tracing::debug!("Dropped {dialect:?} flag `{shown}`"),
+            Unsupported => eprintln!(
+                "wild-proxy: warning: `{shown}` isn't supported by Wild and was dropped from the link"
+            ),
+        }
+    }
+    translated
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn args(line: &str) -> Vec<OsString> {
+        crate::shell::split(line.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn detect_dialect() {
+        for (linker, dialect) in [
+            ("/usr/lib/gcc/x86_64-linux-gnu/12/collect2", Bfd),
+            ("/usr/bin/ld", Bfd),
+            ("/usr/bin/ld.bfd", Bfd),
+            ("/usr/bin/ld.gold", Gold),
+            ("/usr/bin/ld.lld", Lld),
+            ("/usr/lib/llvm-19/bin/ld.lld", Lld),
+            (
+                "/opt/android-ndk/toolchains/llvm/prebuilt/linux-x86_64/bin/lld",
+                Lld,
+            ),
+        ] {
+            assert_eq!(dialect, Dialect::detect(Path::new(linker)), "{linker}");
+        }
+    }
+
+    #[test]
+    fn translate_lld() {
+        assert_eq!(
+            args(
+                "--hash-style=gnu -z pack-relative-relocs -z now -o out main.o -lc --threads=4 \
+                 -z relro"
+            ),
+            translate(
+                args(
+                    "--hash-style=gnu --pack-dyn-relocs=relr -z rel -z now -o out --rosegment \
+                     main.o --symbol-ordering-file order.txt -lc -zrel --lto-O3 --threads=4 \
+                     -error-limit=0 -z relro"
+                ),
+                Lld
+            )
+        );
+    }
+
+    #[test]
+    fn translate_gold_and_bfd() {
+        assert_eq!(
+            args("--icf=all --thread-count 8 -o out main.o"),
+            translate(
+                args("--icf=all --thread-count 8 -o out --detect-odr-violations main.o"),
+                Gold
+            )
+        );
+        assert_eq!(
+            args("-o out main.o"),
+            translate(
+                args(
+                    "--no-warn-rwx-segments -o out main.o --compress-debug-sections=none \
+                     --package-metadata={}"
+                ),
+                Bfd
+            )
+        );
+        // Other dialects' flags are left for Wild to judge.
+        let lld_only = args("--pack-dyn-relocs=relr -z rel");
+        assert_eq!(lld_only, translate(lld_only.clone(), Bfd));
+    }
+}
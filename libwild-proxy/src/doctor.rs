@@ -0,0 +1,138 @@
+//! `wild-proxy doctor <driver> <args...>` explains how an invocation would be handled. Apart from
+//! asking the compiler for its `-###` dump, which doesn't compile anything, nothing is run.
+
+use crate::{
+    FailurePolicy, LinkPlan, LinkerProbe, filter_driver_args, obtain_whole_command,
+    resolve_compiler,
+};
+use crate::{
+    dialect::Dialect,
+    exec_prefix::{self, PrefixLinkerPolicy},
+    rewrite_rules::Rules,
+    shell,
+    wild_args::WildArgs,
+};
+use anyhow::{Result, bail};
+use std::ffi::OsString;
+
+pub fn doctor(driver_argv: &[OsString]) -> Result<()> {
+    let driver_argv = crate::strip_launcher(driver_argv);
+    if driver_argv.is_empty() {
+        bail!("Usage: wild-proxy doctor <compiler> [args...]");
+    }
+    println!("Invocation: {}", shell::join(driver_argv));
+
+    let chain = crate::recursion::chain()?;
+    let (next_executable, driver_args) = resolve_compiler(driver_argv, &chain)?;
+    let (args, dropped) = filter_driver_args(driver_args);
+    let args = [next_executable.prefix_args.clone(), args].concat();
+    println!("Real compiler: {}", next_executable.path.display());
+    if !next_executable.prefix_args.is_empty() {
+        println!(
+            "Extra compiler arguments: {}",
+            shell::join(&next_executable.prefix_args)
+        );
+    }
+    if let Some(wrapper) = &next_executable.wrapper {
+        println!(
+            "Compiler wrapper: {}, used for compile-only invocations",
+            wrapper.display()
+        );
+    }
+    for (candidate, reason) in &next_executable.skipped {
+        println!("Skipped: {} ({reason})", candidate.display());
+    }
+    if !dropped.is_empty() {
+        println!("Dropped flags: {}", shell::join(&dropped));
+    }
+
+    match crate::linker_probe::detect(&args) {
+        Some(LinkerProbe::Version) => {
+            println!("Linker probe: yes, answered with Wild's version");
+            return Ok(());
+        }
+        Some(LinkerProbe::LinkerPath) => {
+            if let Some(ld) = crate::linker_probe::ld_impostor(&driver_argv[0]) {
+                println!("Linker probe: yes, answered with {}", ld.display());
+                return Ok(());
+            }
+            println!("Linker probe: yes, but there's no `ld` impostor next to this one");
+        }
+        None => {}
+    }
+    if let Some(reason) = crate::pass_through_reason(&args) {
+        println!("Pass-through: yes, `{reason}` is handled by the real compiler");
+        return Ok(());
+    }
+    println!("Pass-through: no");
+
+    let chain = crate::recursion::extend_chain(chain, &next_executable.path)?;
+    let compiler_output = crate::probe::run(&next_executable.path, &args, &chain)?;
+    let raw_dump = compiler_output.stderr;
+    if !compiler_output.status.success() {
+        println!("The compiler rejected the invocation:");
+        crate::probe::lines(&raw_dump)
+            .for_each(|line| println!("  {}", String::from_utf8_lossy(line).trim_end()));
+        return Ok(());
+    }
+
+    let commands = obtain_whole_command(crate::probe::lines(&raw_dump))?;
+    println!("Compile steps:");
+    if commands.build_and_assemble.is_empty() {
+        println!("  (none)");
+    }
+    for step in &commands.build_and_assemble {
+        println!("  {}", String::from_utf8_lossy(step.command));
+    }
+
+    let Some(link) = commands.link else {
+        println!("Link: none, nothing for Wild to do");
+        return Ok(());
+    };
+    println!(
+        "Link command from the compiler: {}",
+        String::from_utf8_lossy(link.command)
+    );
+    let link_args = shell::split(link.command)?;
+    // The same order as an actual link, see `fallback_with`.
+    let plan = LinkPlan::parse(&link_args)?;
+    println!("Linker dialect: {:?}", Dialect::detect(&plan.linker));
+    let rules = Rules::from_env()?;
+    let (wild_args, applied) =
+        crate::wild_args_and_rules(&plan, &rules, &raw_dump, &next_executable.path);
+    if !applied.is_empty() {
+        println!("Rewrite rules applied: {}", applied.join(", "));
+    }
+    if let Some(reason) = crate::capability::unsupported(&plan) {
+        println!("Capability check: {reason}, linked by the system linker");
+        return Ok(());
+    }
+    let prefixes = exec_prefix::user_prefixes(&args, std::env::var_os("GCC_EXEC_PREFIX"));
+    if let Some(linker) = exec_prefix::prefix_linker(&link_args[0], &link.env, &prefixes) {
+        if PrefixLinkerPolicy::from_env()?.delegates_to(&linker) {
+            println!("Linker from -B: {}, used instead of Wild", linker.display());
+            return Ok(());
+        }
+        println!("Linker from -B: {}, replaced by Wild", linker.display());
+    }
+    println!("Wild arguments:");
+    for arg in &wild_args {
+        println!("  {}", arg.to_string_lossy());
+    }
+    match WildArgs::new(&wild_args) {
+        Ok(wild_args) => match libwild::Args::parse(|| wild_args.args.iter()) {
+            Ok(_) => println!("Wild accepts these arguments"),
+            Err(e) => println!("Wild rejects these arguments: {e:?}"),
+        },
+        Err(e) => println!("Wild rejects these arguments: {e}"),
+    }
+    let socket = crate::link_server::socket_path();
+    if std::os::unix::net::UnixStream::connect(&socket).is_ok() {
+        println!("Link server: {}", socket.display());
+    } else {
+        println!("Link server: none, linking in-process");
+    }
+    println!("On failure: {:?}", FailurePolicy::from_env()?);
+
+    Ok(())
+}
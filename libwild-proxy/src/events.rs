@@ -0,0 +1,40 @@
+//! Log of noteworthy events, like Wild failures that were papered over by the system linker. When
+//! `WILD_PROXY_EVENT_LOG` names a file, one tab separated line is appended per event, so rollouts
+//! can be monitored without scraping build logs.
+
+use std::{
+    io::Write,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const EVENT_LOG_ENV: &str = "WILD_PROXY_EVENT_LOG";
+
+/// Records an event of the given kind. Failing to write the log never fails the build.
+pub(crate) fn record(kind: &str, message: &str) {
+    tracing::info!(kind, "{message}");
+    let Some(path) = std::env::var_os(EVENT_LOG_ENV).filter(|path| !path.is_empty()) else {
+        return;
+    };
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+    let directory = std::env::current_dir().unwrap_or_default();
+    // Keep each event on a single line, so the log stays easy to process.
+    let message = message.replace(['\n', '\t'], " ");
+    let line = format!(
+        "{timestamp}\t{}\t{kind}\t{}\t{message}\n",
+        std::process::id(),
+        directory.display()
+    );
+
+    // A single `write` of an append-only file is atomic enough for concurrent builds.
+    let result = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(line.as_bytes()));
+    if let Err(e) = result {
+        tracing::warn!("Failed to write event log `{}`: {e}", path.display());
+    }
+}
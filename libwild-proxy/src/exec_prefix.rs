@@ -0,0 +1,242 @@
+//! `-B<prefix>` and `GCC_EXEC_PREFIX` select the programs the compiler runs. The driver resolves
+//! `as` and the other compile steps through them itself, so the dump already has the right paths,
+//! but `collect2` only looks for `ld` in `COMPILER_PATH` when it runs. A linker selected this way is
+//! either run or replaced by Wild depending on `WILD_PROXY_PREFIX_LINKER`.
+
+use crate::recursion::FileId;
+use anyhow::{Result, bail};
+use std::{
+    ffi::{OsStr, OsString},
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+const PREFIX_LINKER_ENV: &str = "WILD_PROXY_PREFIX_LINKER";
+
+/// What to do with a linker found through a user-supplied prefix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PrefixLinkerPolicy {
+    /// Link in-process if the selected linker is Wild, e.g. `-B ~/Projects/wild`, and with the
+    /// selected linker otherwise. The default.
+    Auto,
+    /// Always run the selected linker.
+    Honour,
+    /// Always link in-process.
+    Wild,
+}
+
+impl PrefixLinkerPolicy {
+    pub(crate) fn from_env() -> Result<Self> {
+        let Some(value) = std::env::var_os(PREFIX_LINKER_ENV) else {
+            return Ok(Self::Auto);
+        };
+        Self::parse(&value.to_string_lossy())
+    }
+
+    fn parse(value: &str) -> Result<Self> {
+        match value {
+            "" | "auto" => Ok(Self::Auto),
+            "honour" | "honor" => Ok(Self::Honour),
+            "wild" => Ok(Self::Wild),
+            other => bail!(
+                "Invalid {PREFIX_LINKER_ENV} value `{other}`, expected `auto`, `honour` or `wild`"
+            ),
+        }
+    }
+
+    /// Returns whether the link should be left to `linker`.
+    pub(crate) fn delegates_to(self, linker: &Path) -> bool {
+        match self {
+            Self::Auto => !is_wild(linker),
+            Self::Honour => true,
+            Self::Wild => false,
+        }
+    }
+}
+
+/// Identifies Wild by what it is rather than where it lives: this proxy itself, or a program that
+/// introduces itself as Wild.
+fn is_wild(linker: &Path) -> bool {
+    let own_id = std::env::current_exe()
+        .ok()
+        .and_then(|exe| FileId::of(&exe));
+    if own_id.is_some() && FileId::of(linker) == own_id {
+        return true;
+    }
+    Command::new(linker)
+        .arg("--version")
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .is_ok_and(|output| output.stdout.starts_with(b"Wild version "))
+}
+
+/// Returns the prefixes given with `-B`, in order, followed by `GCC_EXEC_PREFIX`.
+pub(crate) fn user_prefixes(args: &[OsString], gcc_exec_prefix: Option<OsString>) -> Vec<OsString> {
+    let mut prefixes = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == "-B" {
+            prefixes.extend(iter.next().cloned());
+        } else if let Some(prefix) = arg.as_bytes().strip_prefix(b"-B") {
+            prefixes.push(OsStr::from_bytes(prefix).to_owned());
+        }
+    }
+    prefixes.extend(gcc_exec_prefix.filter(|prefix| !prefix.is_empty()));
+    prefixes
+}
+
+/// Returns the linker the link command runs if it comes from one of `prefixes`. `program` is the
+/// first word of the link command and `env` the environment it runs in.
+pub(crate) fn prefix_linker(
+    program: &OsStr,
+    env: &[(&str, &[u8])],
+    prefixes: &[OsString],
+) -> Option<PathBuf> {
+    if prefixes.is_empty() {
+        return None;
+    }
+    let program = Path::new(program);
+    let linker = if program.file_stem().is_some_and(|stem| stem == "collect2") {
+        // Like `collect2`, prefer `real-ld` anywhere in `COMPILER_PATH` over `ld`.
+        let compiler_path = env
+            .iter()
+            .find_map(|(name, value)| (*name == "COMPILER_PATH").then_some(*value))?;
+        let dirs = std::env::split_paths(OsStr::from_bytes(compiler_path)).collect::<Vec<_>>();
+        ["real-ld", "ld"].iter().find_map(|name| {
+            dirs.iter()
+                .map(|dir| dir.join(name))
+                .find(|path| path.is_file())
+        })?
+    } else {
+        // Clang runs the linker directly.
+        program.to_owned()
+    };
+    prefixes
+        .iter()
+        .any(|prefix| has_prefix(&linker, prefix))
+        .then_some(linker)
+}
+
+/// Returns whether `program` is found through `prefix`. Like GCC, a prefix naming a directory
+/// applies to the programs below it, any other prefix is put in front of the program name.
+fn has_prefix(program: &Path, prefix: &OsStr) -> bool {
+    if prefix.as_bytes().ends_with(b"/") || Path::new(prefix).is_dir() {
+        return program.starts_with(prefix);
+    }
+    program
+        .as_os_str()
+        .as_bytes()
+        .strip_prefix(prefix.as_bytes())
+        .is_some_and(|name| !name.is_empty() && !name.contains(&b'/'))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn parse_policy() {
+        assert_eq!(
+            PrefixLinkerPolicy::Auto,
+            PrefixLinkerPolicy::parse("").unwrap()
+        );
+        assert_eq!(
+            PrefixLinkerPolicy::Honour,
+            PrefixLinkerPolicy::parse("honor").unwrap()
+        );
+        assert_eq!(
+            PrefixLinkerPolicy::Wild,
+            PrefixLinkerPolicy::parse("wild").unwrap()
+        );
+        assert!(PrefixLinkerPolicy::parse("bfd").is_err());
+    }
+
+    #[test]
+    fn identify_wild() {
+        let dir =
+            std::env::temp_dir().join(format!("wild-proxy-identify-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let linker = |name: &str, version: &str| {
+            let path = dir.join(name);
+            std::fs::write(&path, format!("#!/bin/sh\necho '{version}'\n")).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+            path
+        };
+        let wild = linker("ld", "Wild version 0.7.0 (compatible with GNU linkers)");
+        // Named after Wild, but isn't.
+        let bfd = linker("wild-ld", "GNU ld (GNU Binutils for Debian) 2.40");
+        let proxy = std::env::current_exe().unwrap();
+
+        assert!(!PrefixLinkerPolicy::Auto.delegates_to(&wild));
+        assert!(!PrefixLinkerPolicy::Auto.delegates_to(&proxy));
+        assert!(PrefixLinkerPolicy::Auto.delegates_to(&bfd));
+        assert!(PrefixLinkerPolicy::Auto.delegates_to(&dir.join("missing")));
+        assert!(PrefixLinkerPolicy::Honour.delegates_to(&wild));
+        assert!(!PrefixLinkerPolicy::Wild.delegates_to(&bfd));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn prefixes_from_args() {
+        let args = ["-B/opt/a/", "-O2", "-B", "/opt/b", "-Bfoo-", "hello.c"].map(OsString::from);
+        assert_eq!(
+            vec!["/opt/a/", "/opt/b", "foo-", "/opt/gcc/lib/gcc/"],
+            user_prefixes(&args, Some("/opt/gcc/lib/gcc/".into()))
+        );
+        assert!(user_prefixes(&args[1..2], Some("".into())).is_empty());
+    }
+
+    #[test]
+    fn linker_from_prefix() {
+        let dir =
+            std::env::temp_dir().join(format!("wild-proxy-prefix-test-{}", std::process::id()));
+        for sub in ["binutils", "gcc"] {
+            std::fs::create_dir_all(dir.join(sub)).unwrap();
+            std::fs::write(dir.join(sub).join("ld"), "").unwrap();
+        }
+        let binutils = dir.join("binutils").into_os_string();
+        let compiler_path = format!("{}/binutils/:{}/gcc/", dir.display(), dir.display());
+        let env = [("COMPILER_PATH", compiler_path.as_bytes())];
+        let collect2 = OsStr::new("/usr/lib/gcc/x86_64-linux-gnu/12/collect2");
+
+        assert_eq!(
+            Some(dir.join("binutils/ld")),
+            prefix_linker(collect2, &env, std::slice::from_ref(&binutils))
+        );
+        // `real-ld` wins even if it comes later.
+        std::fs::write(dir.join("gcc/real-ld"), "").unwrap();
+        assert_eq!(
+            None,
+            prefix_linker(collect2, &env, std::slice::from_ref(&binutils))
+        );
+        assert_eq!(None, prefix_linker(collect2, &env, &[]));
+
+        let clang_ld = dir.join("binutils/ld").into_os_string();
+        assert_eq!(
+            Some(PathBuf::from(&clang_ld)),
+            prefix_linker(&clang_ld, &[], &[binutils])
+        );
+        assert_eq!(
+            None,
+            prefix_linker(OsStr::new("/usr/bin/ld"), &[], &["/opt/".into()])
+        );
+        // Prefixes match whole directories, or the start of the program name.
+        let binutils_ld = OsStr::new("/opt/binutils/ld");
+        assert_eq!(None, prefix_linker(binutils_ld, &[], &["/opt/b".into()]));
+        assert_eq!(
+            Some(PathBuf::from(binutils_ld)),
+            prefix_linker(binutils_ld, &[], &["/opt/binutils/".into()])
+        );
+        assert_eq!(
+            Some(PathBuf::from("/opt/cross-ld")),
+            prefix_linker(OsStr::new("/opt/cross-ld"), &[], &["/opt/cross-".into()])
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
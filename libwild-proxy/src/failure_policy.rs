@@ -0,0 +1,55 @@
+use anyhow::{Result, bail};
+
+const FAILURE_POLICY_ENV: &str = "WILD_PROXY_ON_FAILURE";
+
+/// What to do when Wild rejects the arguments, fails or panics. Selected with
+/// `WILD_PROXY_ON_FAILURE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FailurePolicy {
+    /// Fail the link, the default.
+    Strict,
+    /// Warn and rerun the original link command with the system linker.
+    Fallback,
+}
+
+impl FailurePolicy {
+    pub(crate) fn from_env() -> Result<Self> {
+        let Some(value) = std::env::var_os(FAILURE_POLICY_ENV) else {
+            return Ok(Self::Strict);
+        };
+        Self::parse(&value.to_string_lossy())
+    }
+
+    fn parse(value: &str) -> Result<Self> {
+        match value {
+            "" | "strict" => Ok(Self::Strict),
+            "fallback" | "warn-and-fallback" => Ok(Self::Fallback),
+            other => bail!(
+                "Invalid {FAILURE_POLICY_ENV} value `{other}`, expected `strict` or `fallback`"
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_policy() {
+        assert_eq!(FailurePolicy::Strict, FailurePolicy::parse("").unwrap());
+        assert_eq!(
+            FailurePolicy::Strict,
+            FailurePolicy::parse("strict").unwrap()
+        );
+        assert_eq!(
+            FailurePolicy::Fallback,
+            FailurePolicy::parse("fallback").unwrap()
+        );
+        assert_eq!(
+            FailurePolicy::Fallback,
+            FailurePolicy::parse("warn-and-fallback").unwrap()
+        );
+        assert!(FailurePolicy::parse("sometimes").is_err());
+    }
+}
@@ -0,0 +1,154 @@
+//! Generation of the impostor symlink farm. `wild-proxy install <dir>` scans `PATH` for compiler
+//! drivers, including versioned (`gcc-14`) and triplet-prefixed (`aarch64-linux-gnu-gcc`) ones, and
+//! linkers (`ld`, `ld.bfd`), and points a symlink with the same name in `<dir>` at the current
+//! binary.
+
+use crate::names::{DriverName, is_linker_name};
+use anyhow::{Context, Result, bail};
+use std::{
+    collections::BTreeSet,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+/// Creates or updates symlinks in `dir` for every compiler driver and linker found in `PATH`.
+/// Symlinks to this binary for programs that are no longer installed are removed.
+pub fn install(dir: &Path) -> Result<()> {
+    let real_exe = current_exe()?;
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create `{}`", dir.display()))?;
+    let dir = dir.canonicalize()?;
+
+    let paths = std::env::var_os("PATH").context("Could not get PATH env variable")?;
+    let search_dirs = std::env::split_paths(&paths)
+        .filter(|search_dir| search_dir.canonicalize().ok().as_deref() != Some(&dir))
+        .collect::<Vec<_>>();
+    let names = installed_compilers(&search_dirs, &real_exe);
+
+    for stale in our_symlinks(&dir, &real_exe)?.into_iter().filter(|path| {
+        !path
+            .file_name()
+            .is_some_and(|name| names.contains(&*name.to_string_lossy()))
+    }) {
+        std::fs::remove_file(&stale)
+            .with_context(|| format!("Failed to remove `{}`", stale.display()))?;
+        println!("Removed {}", stale.display());
+    }
+
+    for name in &names {
+        let link = dir.join(name);
+        match std::fs::symlink_metadata(&link) {
+            Ok(meta) if meta.is_symlink() => {
+                if link.canonicalize().ok().as_deref() == Some(&real_exe) {
+                    continue;
+                }
+                std::fs::remove_file(&link)?;
+            }
+            Ok(_) => {
+                eprintln!("Skipping {}, it's not a symlink", link.display());
+                continue;
+            }
+            Err(_) => {}
+        }
+        std::os::unix::fs::symlink(&real_exe, &link)
+            .with_context(|| format!("Failed to create `{}`", link.display()))?;
+        println!("{} -> {}", link.display(), real_exe.display());
+    }
+
+    if names.is_empty() {
+        bail!("No compilers found in PATH");
+    }
+    Ok(())
+}
+
+/// Removes every symlink in `dir` that points at this binary, and `dir` itself if it ends up empty.
+pub fn uninstall(dir: &Path) -> Result<()> {
+    let real_exe = current_exe()?;
+    for link in our_symlinks(dir, &real_exe)? {
+        std::fs::remove_file(&link)
+            .with_context(|| format!("Failed to remove `{}`", link.display()))?;
+        println!("Removed {}", link.display());
+    }
+    if std::fs::read_dir(dir)?.next().is_none() {
+        std::fs::remove_dir(dir)?;
+    }
+    Ok(())
+}
+
+fn current_exe() -> Result<PathBuf> {
+    std::env::current_exe()
+        .and_then(|exe| exe.canonicalize())
+        .context("Could not get current exe path")
+}
+
+fn our_symlinks(dir: &Path, real_exe: &Path) -> Result<Vec<PathBuf>> {
+    let mut links = Vec::new();
+    for entry in
+        std::fs::read_dir(dir).with_context(|| format!("Failed to read `{}`", dir.display()))?
+    {
+        let path = entry?.path();
+        if path.is_symlink() && path.canonicalize().ok().as_deref() == Some(real_exe) {
+            links.push(path);
+        }
+    }
+    Ok(links)
+}
+
+/// Returns the names of compiler drivers and linkers found in `search_dirs`, skipping this binary.
+fn installed_compilers(search_dirs: &[PathBuf], real_exe: &Path) -> BTreeSet<String> {
+    let mut names = BTreeSet::new();
+    for dir in search_dirs {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if (DriverName::parse(&name).is_none() && !is_linker_name(&name))
+                || names.contains(&name)
+            {
+                continue;
+            }
+            let path = entry.path();
+            let is_executable = std::fs::metadata(&path)
+                .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0);
+            if is_executable && path.canonicalize().ok().as_deref() != Some(real_exe) {
+                names.insert(name);
+            }
+        }
+    }
+    names
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn scan_path() {
+        let dir =
+            std::env::temp_dir().join(format!("wild-proxy-install-test-{}", std::process::id()));
+        let bin = dir.join("bin");
+        std::fs::create_dir_all(&bin).unwrap();
+        for (name, mode) in [
+            ("gcc-14", 0o755),
+            ("clang", 0o755),
+            ("gcc-ar", 0o755),
+            ("ld.bfd", 0o755),
+            ("g++", 0o644),
+        ] {
+            let path = bin.join(name);
+            std::fs::write(&path, "").unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+        }
+        let real_exe = current_exe().unwrap();
+        std::os::unix::fs::symlink(&real_exe, bin.join("cc")).unwrap();
+
+        let names = installed_compilers(&[bin, dir.join("missing")], &real_exe);
+        assert_eq!(
+            vec!["clang", "gcc-14", "ld.bfd"],
+            names.iter().map(String::as_str).collect::<Vec<_>>()
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
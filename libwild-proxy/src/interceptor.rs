@@ -0,0 +1,34 @@
+//! Hooks for tools that embed the proxy, see [`crate::fallback_with`].
+
+use crate::LinkPlan;
+use anyhow::Result;
+use std::{ffi::OsString, process::ExitStatus};
+
+/// Callbacks around the steps of an invocation that links. Invocations that don't link, like `-c`,
+/// `-S` or `-E`, are handed to the real compiler as they are and never reach them. All of them do
+/// nothing by default, so implementations only override what they need. Errors returned from a
+/// callback stop the invocation.
+pub trait LinkInterceptor {
+    /// Called before each compile or assemble step of an invocation that also links, e.g.
+    /// `cc main.c -o main`. `command` starts with the program and can be changed.
+    fn before_compile_step(&mut self, command: &mut Vec<OsString>) -> Result<()> {
+        let _ = command;
+        Ok(())
+    }
+
+    /// Called once the link is known and before anything is linked. Changes to `plan` are linked
+    /// instead, by Wild as well as by the system linker.
+    fn link_planned(&mut self, plan: &mut LinkPlan) -> Result<()> {
+        let _ = plan;
+        Ok(())
+    }
+
+    /// Called after the link with the exit status of the linker, successful when Wild linked
+    /// in-process, or the error that stopped the link.
+    fn after_link(&mut self, plan: &LinkPlan, result: &Result<ExitStatus>) {
+        let _ = (plan, result);
+    }
+}
+
+/// No hooks, what [`crate::fallback`] uses.
+impl LinkInterceptor for () {}
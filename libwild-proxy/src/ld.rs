@@ -0,0 +1,75 @@
+//! The `ld` impostor, for builds that call the linker directly like kernel-style Makefiles or
+//! firmware. There's no compiler driver to ask, so the arguments go straight to libwild.
+//! Invocations Wild can't handle, like `$(LD) -r` steps, go to the real `ld` further down `PATH`.
+
+use crate::{
+    FailurePolicy, capability,
+    dialect::{self, Dialect},
+    link_plan::LinkPlan,
+    recursion,
+    wild_args::WildArgs,
+};
+use anyhow::{Result, anyhow};
+use std::{os::unix::process::CommandExt, path::Path, process::Command};
+
+/// Returns whether the binary was invoked as a linker, e.g. `ld`, `ld.bfd` or
+/// `x86_64-linux-gnu-ld`.
+pub fn is_invoked_as_ld() -> bool {
+    std::env::args_os().next().is_some_and(|arg| {
+        std::path::Path::new(&arg)
+            .file_name()
+            .is_some_and(|name| crate::names::is_linker_name(&name.to_string_lossy()))
+    })
+}
+
+pub fn ld() -> Result<()> {
+    let argv = std::env::args_os().collect::<Vec<_>>();
+    let linker_args = &argv[1..];
+
+    // Under a proxy, the system linker was asked for explicitly, e.g. by `collect2` when falling
+    // back.
+    let chain = recursion::chain()?;
+    if chain.is_empty() {
+        let dialect = Dialect::detect(Path::new(&argv[0]));
+        let translated = dialect::translate(linker_args.to_vec(), dialect);
+        let unsupported = LinkPlan::parse(&[&argv[..1], &translated].concat())
+            .map(|plan| capability::unsupported(&plan));
+        let parsed = match unsupported {
+            Ok(Some(reason)) => Err(anyhow!(reason)),
+            _ => Ok(()),
+        }
+        .and_then(|()| WildArgs::new(&translated))
+        .and_then(|wild_args| {
+            let parsed =
+                libwild::Args::parse(|| wild_args.args.iter()).map_err(|e| anyhow!("{e:?}"))?;
+            Ok((wild_args, parsed))
+        });
+        match parsed {
+            Ok((mut wild_args, parsed)) => {
+                let Err(error) = crate::catch_wild_panic(|| libwild::run(parsed))
+                    .and_then(|()| wild_args.finish())
+                else {
+                    return Ok(());
+                };
+                if FailurePolicy::from_env()? == FailurePolicy::Strict {
+                    return Err(error);
+                }
+                crate::warn_system_linker_fallback(&error, &argv[0]);
+            }
+            Err(error) => {
+                tracing::debug!(
+                    "Wild doesn't support this invocation, using the system ld: {error:?}"
+                );
+            }
+        }
+    }
+
+    let ld = crate::find_next_executable(&argv[0], &chain)?.path;
+    let chain = recursion::extend_chain(chain, &ld)?;
+    // Exec doesn't return if successful
+    let err = Command::new(&ld)
+        .args(linker_args)
+        .env(recursion::CHAIN_ENV, chain)
+        .exec();
+    Err(anyhow!("Failed to exec linker {}: {}", ld.display(), err))
+}
@@ -1,14 +1,54 @@
+mod capability;
+mod compile_cache;
+mod compile_db;
+mod dialect;
+mod doctor;
+mod events;
+mod exec_prefix;
+mod failure_policy;
+mod install;
+mod interceptor;
+mod ld;
+mod link_cache;
+mod link_plan;
+mod link_server;
+mod linker_probe;
+mod names;
+mod output_cache;
 mod outputs_cleanup;
+mod probe;
+mod recursion;
+mod repro;
+mod rewrite_rules;
+mod shadow;
+mod shell;
+mod wild_args;
 
 use anyhow::{Context, Result, anyhow, bail};
+use compile_cache::CachedStep;
+use dialect::Dialect;
+pub use doctor::doctor;
+use failure_policy::FailurePolicy;
+pub use install::{install, uninstall};
+pub use interceptor::LinkInterceptor;
+pub use ld::{is_invoked_as_ld, ld};
+pub use link_plan::{InputState, LinkArg, LinkInput, LinkInputKind, LinkPlan};
+pub use link_server::serve;
+use linker_probe::LinkerProbe;
+use output_cache::OutputCache;
 use outputs_cleanup::DeleteOutputs;
+pub use repro::replay;
+use rewrite_rules::Rules;
 use std::{
+    ffi::{OsStr, OsString},
+    io::Write,
+    os::unix::ffi::OsStrExt,
     os::unix::fs::PermissionsExt,
     os::unix::process::CommandExt,
     path::{Path, PathBuf},
-    process::{Command, exit},
-    str::Lines,
+    process::{Command, ExitStatus, exit},
 };
+use wild_args::WildArgs;
 
 // TODOs:
 // - Implement the TODOs
@@ -19,25 +59,51 @@ use std::{
 
 /// Fallback and ask the OG linker if we cannot figure it out ourselves
 pub fn fallback() -> Result<()> {
+    fallback_with(&mut ())
+}
+
+/// Like [`fallback`], calling `interceptor` around the compile steps and the link.
+pub fn fallback_with(interceptor: &mut dyn LinkInterceptor) -> Result<()> {
     let mut files_to_delete = DeleteOutputs::with_capacity(2);
-    let mut exe_with_args = std::env::args();
-    let zero_position_arg = exe_with_args
-        .next()
-        .context("Could not obtain binary name from args")?;
-    let args = exe_with_args
-        .filter(|s| !s.starts_with("-fuse-ld="))
-        .collect::<Vec<_>>();
+    let full_argv = std::env::args_os().collect::<Vec<_>>();
+    let driver_argv = strip_launcher(&full_argv);
+    let chain = recursion::chain()?;
+    let (next_executable, driver_args) = resolve_compiler(driver_argv, &chain)?;
+    let (args, _) = filter_driver_args(driver_args);
+    let compiler_path = next_executable.path;
+    let args = [next_executable.prefix_args, args].concat();
+    let nested = !chain.is_empty();
+    let chain = recursion::extend_chain(chain, &compiler_path)?;
 
-    let compiler_path = find_next_executable(&zero_position_arg)?;
-    let mut compiler_command = Command::new(&compiler_path);
+    if !nested {
+        compile_db::record_compile(&compiler_path, &shell::lossy(&args));
+    }
 
-    if args.iter().any(|arg| {
-        ["--help", "--version", "-###", "-c"].contains(&arg.as_str())
-            || arg.starts_with("-dump")
-            || arg.starts_with("-print")
-    }) {
+    if !nested && let Some(probe) = linker_probe::detect(&args) {
+        match probe {
+            LinkerProbe::Version => return linker_probe::print_identity(),
+            LinkerProbe::LinkerPath => {
+                if let Some(ld) = linker_probe::ld_impostor(&full_argv[0]) {
+                    println!("{}", ld.display());
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    // A proxy further up the chain already takes care of linking.
+    if nested || pass_through_reason(&args).is_some() {
+        // Compile-only invocations go through ccache and the like, so caching still works.
+        let mut command = match next_executable.wrapper {
+            Some(wrapper) if !nested && pass_through_reason(&args) == Some("-c") => {
+                let mut command = Command::new(wrapper);
+                command.arg(&compiler_path);
+                command
+            }
+            _ => Command::new(&compiler_path),
+        };
         // Exec doesn't return if successful
-        let err = compiler_command.args(&args).exec();
+        let err = command.args(&args).env(recursion::CHAIN_ENV, &chain).exec();
         return Err(anyhow!(
             "Failed to exec compiler {}: {}",
             compiler_path.display(),
@@ -45,40 +111,64 @@ pub fn fallback() -> Result<()> {
         ));
     }
 
-    let compiler_output = compiler_command
-        .args(&args)
-        .arg("-###")
-        .output()
-        .with_context(|| format!("Failed to run {}", compiler_path.display()))?;
-    if !compiler_output.status.success() {
-        String::from_utf8_lossy(&compiler_output.stderr)
-            .lines()
-            .filter(|line| line.contains("error: "))
-            .for_each(|error_line| eprintln!("{}", error_line.trim_end()));
-        if let Some(code) = compiler_output.status.code() {
-            exit(code);
-        } else {
-            return Ok(());
-        }
-    }
-    let raw_dump = String::from_utf8(compiler_output.stderr)?;
+    let prefixes = exec_prefix::user_prefixes(&args, std::env::var_os("GCC_EXEC_PREFIX"));
+    let verbose = args.iter().any(|arg| arg == "-v");
+    let compiler_output = probe::run(&compiler_path, &args, &chain)?;
+    let raw_dump = probe::check(&compiler_path, &args, &chain, compiler_output, verbose)?;
+    let rules = Rules::from_env()?;
 
-    let commands = obtain_whole_command(raw_dump.lines())
+    let mut commands = obtain_whole_command(probe::lines(&raw_dump))
         .with_context(|| format!("Invocation args: {args:?}"))?;
+    // Every step and the link run with the chain, in case they end up calling a proxy again.
+    for step in commands
+        .build_and_assemble
+        .iter_mut()
+        .chain(&mut commands.link)
+    {
+        step.env.push((recursion::CHAIN_ENV, chain.as_bytes()));
+    }
+    if args.iter().any(|arg| arg == "-###") {
+        let link = commands.link.as_ref().map(|link| link.command);
+        std::io::stderr().write_all(&dry_run_dump(&raw_dump, link, &rules, &compiler_path)?)?;
+        return Ok(());
+    }
+    // Like the compiler driver, show the version banner and each command as it runs.
+    if verbose {
+        probe::lines(&raw_dump)
+            .take_while(|line| !line.starts_with(b" "))
+            .filter(|line| !line.starts_with(b"COLLECT_GCC_OPTIONS="))
+            .for_each(echo);
+    }
+    let compile_cache =
+        OutputCache::from_env(compile_cache::CACHE_DIR_ENV, compile_cache::CACHE_SIZE_ENV)?;
     let mut steps_iterator = commands.build_and_assemble.into_iter().peekable();
-    while let Some(command) = steps_iterator.next() {
-        let args = shell_words::split(command)?;
-        let exit_status = Command::new(args.first().unwrap())
-            .args(&args[1..])
-            .status()?;
-
-        if !exit_status.success() {
-            if let Some(code) = exit_status.code() {
-                exit(code);
+    while let Some(step) = steps_iterator.next() {
+        let mut args = shell::split(step.command)?;
+        let parsed_args = args.clone();
+        interceptor.before_compile_step(&mut args)?;
+        if verbose {
+            if args == parsed_args {
+                echo(&[b" ", step.command].concat());
             } else {
-                return Ok(());
+                eprintln!(" {}", shell::join(&args));
             }
         }
+        let succeeded = match compile_cache
+            .as_ref()
+            .and_then(|cache| CachedStep::new(cache, &args, &step.env))
+        {
+            Some(cached) if cached.restore() => true,
+            Some(cached) => {
+                let status = cached
+                    .run(&args, &step.env)
+                    .with_context(|| format!("Failed to run {}", args[0].to_string_lossy()))?;
+                step_succeeded(status)
+            }
+            None => run_step(&args, &step.env)?,
+        };
+        if !succeeded {
+            return Ok(());
+        }
 
         // Add output files from intermediate steps to clean up.
         if steps_iterator.peek().is_some() || commands.link.is_some() {
@@ -91,68 +181,544 @@ pub fn fallback() -> Result<()> {
         }
     }
 
-    let mut wild_result = Ok(());
+    if let Some(Step { command, env }) = commands.link {
+        let mut args = shell::split(command)?;
+        let mut command = command.to_vec();
+        let mut plan = LinkPlan::parse(&args)?;
+        let parsed_plan = plan.clone();
+        interceptor.link_planned(&mut plan)?;
+        if plan != parsed_plan {
+            args = [vec![plan.linker.clone().into_os_string()], plan.to_args()].concat();
+            command = shell::join(&args).into_bytes();
+        }
+        let (wild_args, applied_rules) =
+            wild_args_and_rules(&plan, &rules, &raw_dump, &compiler_path);
+        if verbose {
+            for rule in applied_rules {
+                eprintln!("wild-proxy: applied rewrite rule `{rule}`");
+            }
+        }
+        let result = if let Some(reason) = capability::unsupported(&plan) {
+            if verbose {
+                eprintln!(
+                    "wild-proxy: linking with {}, Wild doesn't support this link: {reason}",
+                    args[0].to_string_lossy()
+                );
+                echo(&[b" ", command.as_slice()].concat());
+            }
+            run_command(&args, &env).map(|status| (status, LinkedBy::System))
+        } else if let Some(linker) = delegated_linker(&args, &env, &prefixes)? {
+            // Never served from the cache either, the user asked for this linker.
+            if verbose {
+                eprintln!("wild-proxy: using {}, selected with -B", linker.display());
+                echo(&[b" ", command.as_slice()].concat());
+            }
+            run_command(&args, &env).map(|status| (status, LinkedBy::System))
+        } else {
+            let cache =
+                OutputCache::from_env(link_cache::CACHE_DIR_ENV, link_cache::CACHE_SIZE_ENV)?;
+            let key = cache
+                .as_ref()
+                .and_then(|_| link_cache::key(&plan, &wild_args));
+            let output = plan.output.clone().unwrap_or_else(|| "a.out".into());
+            if let (Some(cache), Some(key)) = (&cache, &key)
+                && cache.restore(key, &output)
+            {
+                Ok((ExitStatus::default(), LinkedBy::Wild))
+            } else {
+                let result = link(&wild_args, &args, &command, &env, driver_argv, verbose);
+                // Only Wild's outputs are cached, they're keyed by its arguments.
+                if let (Some(cache), Some(key), Ok((status, LinkedBy::Wild))) =
+                    (&cache, &key, &result)
+                    && status.success()
+                {
+                    cache.store(key, &output);
+                }
+                result
+            }
+        };
+        if let Ok((status, linked_by)) = &result
+            && status.success()
+        {
+            match linked_by {
+                LinkedBy::Wild => compile_db::record_link("wild", &shell::lossy(&wild_args)),
+                LinkedBy::System => {
+                    compile_db::record_link(&args[0].to_string_lossy(), &shell::lossy(&args[1..]))
+                }
+            }
+        }
+        let result = result.map(|(status, _)| status);
+        interceptor.after_link(&plan, &result);
+        let status = result?;
+        if !status.success()
+            && let Some(code) = status.code()
+        {
+            exit(code);
+        }
+    }
+
+    Ok(())
+}
+
+/// Which linker produced the output of a link.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LinkedBy {
+    /// Wild, in-process or in the link server.
+    Wild,
+    /// The link command of the compiler driver.
+    System,
+}
+
+/// Returns the linker selected with `-B` or `GCC_EXEC_PREFIX` if the link is left to it.
+fn delegated_linker(
+    args: &[OsString],
+    env: &[(&str, &[u8])],
+    prefixes: &[OsString],
+) -> Result<Option<PathBuf>> {
+    let Some(linker) = exec_prefix::prefix_linker(&args[0], env, prefixes) else {
+        return Ok(None);
+    };
+    Ok(exec_prefix::PrefixLinkerPolicy::from_env()?
+        .delegates_to(&linker)
+        .then_some(linker))
+}
+
+/// Links with Wild, or with the system linker if it has to, and returns the exit status of the
+/// linker along with which one produced the output.
+fn link(
+    wild_args: &[OsString],
+    args: &[OsString],
+    command: &[u8],
+    env: &[(&str, &[u8])],
+    driver_argv: &[OsString],
+    verbose: bool,
+) -> Result<(ExitStatus, LinkedBy)> {
+    let failure_policy = FailurePolicy::from_env()?;
+    let shadow = shadow::from_env()?;
+
+    if verbose {
+        if shadow == Some(shadow::Primary::System) {
+            echo(&[b" ", command].concat());
+        } else {
+            eprintln!(" {}", wild_command_line(wild_args));
+        }
+    }
+    if shadow == Some(shadow::Primary::System) {
+        let status = run_command(args, env)?;
+        if status.success() {
+            shadow::run_secondary(shadow::Primary::System, args, wild_args, env);
+        }
+        return Ok((status, LinkedBy::System));
+    } else if let Err(error) =
+        link_server::link(wild_args, verbose).unwrap_or_else(|| run_wild(wild_args))
+    {
+        if let Some(dir) = repro::repro_dir() {
+            // Bundles name files in UTF-8, paths that aren't are only shown lossily.
+            match repro::write_bundle(
+                &dir,
+                &shell::lossy(driver_argv),
+                &shell::lossy(wild_args),
+                &format!("{error:?}"),
+            ) {
+                Ok(bundle) => eprintln!("Reproducer written to {}", bundle.display()),
+                Err(e) => eprintln!("Failed to write reproducer: {e:?}"),
+            }
+        }
+        if failure_policy == FailurePolicy::Strict {
+            return Err(error);
+        }
+
+        warn_system_linker_fallback(&error, &args[0]);
+        if verbose {
+            echo(&[b" ", command].concat());
+        }
+        return run_command(args, env).map(|status| (status, LinkedBy::System));
+    } else if shadow == Some(shadow::Primary::Wild) {
+        shadow::run_secondary(shadow::Primary::Wild, args, wild_args, env);
+    }
+    Ok((ExitStatus::default(), LinkedBy::Wild))
+}
+
+/// Splits driver arguments into the ones passed to the compiler and the ones dropped because Wild
+/// does the linking.
+fn filter_driver_args(args: &[OsString]) -> (Vec<OsString>, Vec<OsString>) {
+    args.iter()
+        .cloned()
+        .partition(|arg| !arg.as_bytes().starts_with(b"-fuse-ld="))
+}
+
+/// Returns the argument that makes the compiler handle the whole invocation on its own, without us
+/// getting involved in linking.
+fn pass_through_reason(args: &[OsString]) -> Option<&str> {
+    args.iter().filter_map(|arg| arg.to_str()).find(|arg| {
+        ["--help", "--version", "-c"].contains(arg)
+            || arg.starts_with("-dump")
+            || arg.starts_with("-print")
+    })
+}
+
+/// The in-process link shown as if it was a command, for `-v` and `-###`.
+fn wild_command_line(linker_args: &[OsString]) -> String {
+    format!("wild {}", shell::join(linker_args))
+}
+
+/// Returns the arguments Wild links `plan` with, after translating flags of other linkers and the
+/// rewrite rules.
+fn wild_args(plan: &LinkPlan, rules: &Rules, raw_dump: &[u8], compiler: &Path) -> Vec<OsString> {
+    wild_args_and_rules(plan, rules, raw_dump, compiler).0
+}
 
-    if let Some(command) = commands.link {
-        let args = shell_words::split(command)?;
-        let wild_args = libwild::Args::parse(|| args.iter().skip(1));
+/// Like [`wild_args`], also returning the names of the rules that applied.
+fn wild_args_and_rules<'a>(
+    plan: &LinkPlan,
+    rules: &'a Rules,
+    raw_dump: &[u8],
+    compiler: &Path,
+) -> (Vec<OsString>, Vec<&'a str>) {
+    let target = probe::target(raw_dump);
+    let scope = rewrite_rules::Scope {
+        target: target.as_deref(),
+        output: plan.output.as_deref(),
+        compiler,
+    };
+    let args = dialect::translate(plan.to_args(), Dialect::detect(&plan.linker));
+    rules.apply(args, &scope)
+}
 
-        match wild_args {
-            Ok(wild_args) => {
-                // Need to clean up temp files
-                // unsafe { libwild::run_in_subprocess(&wild_args) }
-                wild_result = libwild::run(wild_args);
+/// Returns the dump for a user-supplied `-###`, with the link command replaced by the Wild link we
+/// would run instead.
+fn dry_run_dump(
+    raw_dump: &[u8],
+    link: Option<&[u8]>,
+    rules: &Rules,
+    compiler: &Path,
+) -> Result<Vec<u8>> {
+    let wild_line = link
+        .map(|link| {
+            let plan = LinkPlan::parse(&shell::split(link)?)?;
+            anyhow::Ok(wild_command_line(&wild_args(
+                &plan, rules, raw_dump, compiler,
+            )))
+        })
+        .transpose()?;
+    let mut dump = Vec::with_capacity(raw_dump.len());
+    for line in probe::lines(raw_dump) {
+        match (&wild_line, link) {
+            (Some(wild_line), Some(link)) if line.trim_ascii() == link => {
+                dump.push(b' ');
+                dump.extend_from_slice(wild_line.as_bytes());
             }
-            Err(e) => wild_result = Err(e),
+            _ => dump.extend_from_slice(line),
+        }
+        dump.push(b'\n');
+    }
+    Ok(dump)
+}
+
+/// Prints a line from the dump as is, paths in it may not be UTF-8.
+fn echo(line: &[u8]) {
+    let mut stderr = std::io::stderr().lock();
+    let _ = stderr
+        .write_all(line)
+        .and_then(|()| stderr.write_all(b"\n"));
+}
+
+/// Runs a single command from the dump. Exits with its exit code if it fails and returns whether it
+/// succeeded, which is only `false` if it was killed by a signal.
+fn run_step(args: &[OsString], env: &[(&str, &[u8])]) -> Result<bool> {
+    Ok(step_succeeded(run_command(args, env)?))
+}
+
+/// Exits with the exit code of a failed step.
+fn step_succeeded(exit_status: ExitStatus) -> bool {
+    if !exit_status.success() {
+        if let Some(code) = exit_status.code() {
+            exit(code);
+        } else {
+            return false;
         }
     }
+    true
+}
 
-    wild_result.map_err(|e| anyhow!("{e:?}"))
+/// Runs a command from the dump and returns its exit status.
+fn run_command(args: &[OsString], env: &[(&str, &[u8])]) -> Result<ExitStatus> {
+    let program = args.first().context("Empty command")?;
+    Command::new(program)
+        .args(&args[1..])
+        .envs(env_vars(env))
+        .status()
+        .with_context(|| format!("Failed to run {}", program.to_string_lossy()))
 }
 
-fn find_next_executable(zero_position_arg: &str) -> Result<PathBuf> {
-    let mut wanted_exe = Path::new(zero_position_arg)
-        .file_stem()
-        .context("args[0] has no file stem")?;
+/// Links with libwild, turning panics into errors so callers can react to them.
+fn run_wild(linker_args: &[OsString]) -> Result<()> {
+    let mut wild_args = WildArgs::new(linker_args)?;
+    catch_wild_panic(|| {
+        let wild_args = libwild::Args::parse(|| wild_args.args.iter())?;
+        // Need to clean up temp files
+        // unsafe { libwild::run_in_subprocess(&wild_args) }
+        libwild::run(wild_args)
+    })?;
+    wild_args.finish()
+}
+
+/// Tells the user that Wild failed and the link goes to `linker`, and records the event.
+fn warn_system_linker_fallback(error: &anyhow::Error, linker: &OsStr) {
+    let wild_error = error.to_string();
+    let wild_error = wild_error.lines().next().unwrap_or_default();
+    eprintln!(
+        "wild-proxy: warning: Wild failed ({wild_error}), linking with {} instead",
+        linker.to_string_lossy()
+    );
+    events::record(
+        "system-linker-fallback",
+        &format!("Wild failed to link: {error:#}"),
+    );
+}
+
+fn catch_wild_panic(f: impl FnOnce() -> libwild::error::Result) -> Result<()> {
+    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)) {
+        Ok(wild_result) => wild_result.map_err(|e| anyhow!("{e:?}")),
+        Err(payload) => {
+            let message = payload
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("unknown panic payload");
+            bail!("Wild panicked: {message}")
+        }
+    }
+}
+
+/// Returns whether the binary was invoked under its own name rather than through an impostor name
+/// like `gcc`.
+pub fn is_invoked_directly() -> bool {
+    let zero_position_arg = std::env::args_os().next();
+    let real_exe = std::env::current_exe().ok();
+    match (zero_position_arg, real_exe) {
+        (Some(arg), Some(exe)) => Path::new(&arg).file_stem() == exe.file_stem(),
+        _ => false,
+    }
+}
+
+/// Drops our own name when used as a compiler launcher, e.g. `wild-proxy gcc foo.c -o foo` or
+/// `wild-proxy ccache g++ ...`, so the compiler becomes args[0]. Other invocations, including
+/// `wild-proxy foo.c`, are returned unchanged.
+fn strip_launcher(driver_argv: &[OsString]) -> &[OsString] {
+    let real_exe = std::env::current_exe().ok();
+    match driver_argv {
+        [zero_position_arg, compiler, ..]
+            if Path::new(zero_position_arg).file_stem()
+                == real_exe.as_deref().and_then(Path::file_stem)
+                && compiler.to_str().is_some_and(names::is_compiler) =>
+        {
+            &driver_argv[1..]
+        }
+        _ => driver_argv,
+    }
+}
+
+/// The real compiler found in `PATH` together with the candidates that were passed over.
+struct NextExecutable {
+    path: PathBuf,
+    /// Arguments that go before the user's, see [`names::Candidate::prefix_args`].
+    prefix_args: Vec<OsString>,
+    /// A compiler wrapper like ccache that compile-only invocations should go through.
+    wrapper: Option<PathBuf>,
+    skipped: Vec<(PathBuf, &'static str)>,
+}
+
+/// Resolves the real compiler for `driver_argv` and returns it together with the arguments meant
+/// for it. For `ccache gcc ...` and the like, the wrapper is kept for compile-only invocations.
+fn resolve_compiler<'a>(
+    driver_argv: &'a [OsString],
+    chain: &[PathBuf],
+) -> Result<(NextExecutable, &'a [OsString])> {
+    let paths = std::env::var_os("PATH").context("Could not get PATH env variable")?;
     let real_exe = std::env::current_exe().context("Could not get current exe path")?;
-    let wrapper_name = real_exe
-        .file_stem()
-        .context("Current exe has no file stem")?;
-    if wanted_exe == wrapper_name {
-        wanted_exe = "cc".as_ref();
+    resolve_compiler_in(driver_argv, chain, &paths, &real_exe)
+}
+
+fn resolve_compiler_in<'a>(
+    driver_argv: &'a [OsString],
+    chain: &[PathBuf],
+    paths: &OsStr,
+    real_exe: &Path,
+) -> Result<(NextExecutable, &'a [OsString])> {
+    let zero_position_arg = driver_argv
+        .first()
+        .context("Could not obtain binary name from args")?;
+    if let [wrapper, compiler, ..] = driver_argv
+        && wrapper.to_str().is_some_and(names::is_compiler_wrapper)
+    {
+        let wrapper_path = if has_dir(wrapper) {
+            Some(PathBuf::from(wrapper))
+        } else {
+            std::env::split_paths(paths)
+                .map(|dir| dir.join(wrapper))
+                .find(|path| path.is_file())
+        }
+        .with_context(|| format!("Could not find {} in PATH", wrapper.to_string_lossy()))?;
+        let mut next_executable = find_next_executable_in(compiler, chain, paths, real_exe)?;
+        next_executable.wrapper = Some(wrapper_path);
+        return Ok((next_executable, &driver_argv[2..]));
     }
+    let next_executable = find_next_executable_in(zero_position_arg, chain, paths, real_exe)?;
+    Ok((next_executable, &driver_argv[1..]))
+}
+
+fn find_next_executable(zero_position_arg: &OsStr, chain: &[PathBuf]) -> Result<NextExecutable> {
     let paths = std::env::var_os("PATH").context("Could not get PATH env variable")?;
-    for dir in std::env::split_paths(&paths) {
-        let candidate = dir.join(wanted_exe);
-        if let Ok(meta) = std::fs::symlink_metadata(&candidate) {
-            let mode = meta.permissions().mode();
+    let real_exe = std::env::current_exe().context("Could not get current exe path")?;
+    find_next_executable_in(zero_position_arg, chain, &paths, &real_exe)
+}
+
+fn find_next_executable_in(
+    zero_position_arg: &OsStr,
+    chain: &[PathBuf],
+    paths: &OsStr,
+    real_exe: &Path,
+) -> Result<NextExecutable> {
+    let invoked_name = Path::new(zero_position_arg)
+        .file_name()
+        .context("args[0] has no file name")?
+        .to_string_lossy();
+    let wrapper_name = real_exe
+        .file_name()
+        .context("Current exe has no file name")?
+        .to_string_lossy();
+    let own_id = recursion::FileId::of(real_exe).context("Could not stat current exe")?;
+    // A launcher may be given the exact compiler to use.
+    let candidates = if has_dir(zero_position_arg)
+        && recursion::FileId::of(Path::new(zero_position_arg)) != Some(own_id)
+    {
+        vec![names::Candidate::new(zero_position_arg)]
+    } else {
+        names::candidates(&invoked_name, &wrapper_name, |var| std::env::var(var).ok())
+    };
+    let mut skipped = Vec::new();
+    let mut wrapper = None;
+    for candidate in &candidates {
+        let locations = if has_dir(&candidate.name) {
+            vec![PathBuf::from(&candidate.name)]
+        } else {
+            std::env::split_paths(paths)
+                .map(|dir| dir.join(&candidate.name))
+                .collect()
+        };
+        for location in locations {
+            let Ok(meta) = std::fs::metadata(&location) else {
+                continue;
+            };
+            let canonical = location.canonicalize().ok();
             // Owner, group or others executable and not this wrapper?
-            if mode & 0o111 != 0
-                && (!meta.is_symlink()
-                    || candidate
-                        .read_link()
-                        .is_ok_and(|path| path.file_stem() != Some(wrapper_name)))
+            if !meta.is_file() || meta.permissions().mode() & 0o111 == 0 {
+                skipped.push((location, "not executable"));
+            } else if let Some(reason) = recursion::skip_reason(&location, own_id, chain) {
+                if reason == "this wrapper" {
+                    // A compiler wrapper earlier in PATH than us has already run.
+                    wrapper = None;
+                }
+                skipped.push((location, reason));
+            } else if let Some(canonical) = canonical.filter(|canonical| {
+                canonical
+                    .file_name()
+                    .is_some_and(|name| names::is_compiler_wrapper(&name.to_string_lossy()))
+            }) {
+                // ccache and friends in masquerade mode, e.g. /usr/lib/ccache/gcc
+                wrapper.get_or_insert(canonical);
+                skipped.push((location, "compiler wrapper"));
+            } else if let Some(version) = &candidate.version
+                && !reports_version(&location, version)
             {
-                return Ok(candidate);
+                skipped.push((location, "different version"));
+            } else {
+                return Ok(NextExecutable {
+                    path: location,
+                    prefix_args: candidate.prefix_args.iter().map(OsString::from).collect(),
+                    wrapper,
+                    skipped,
+                });
             }
         }
     }
+    let wanted_exe = candidates[0].name.to_string_lossy();
+    if skipped.is_empty() {
+        bail!("Could not find {wanted_exe} other than this wrapper in PATH");
+    }
+    // Candidates that lead back to us are the interesting part when there's a loop.
     bail!(
-        "Could not find {} other than this wrapper in PATH",
-        wanted_exe.display()
+        "Could not find {wanted_exe} other than this wrapper in PATH, skipped: {}",
+        skipped
+            .iter()
+            .map(|(path, reason)| format!("{} ({reason})", path.display()))
+            .collect::<Vec<_>>()
+            .join(", ")
     );
 }
 
+/// Returns whether a program name is a path rather than something to look up in `PATH`.
+fn has_dir(program: &OsStr) -> bool {
+    program.as_bytes().contains(&b'/')
+}
+
+/// Returns whether the compiler at `path` is of the given version. GCC only prints the major
+/// version for `-dumpversion`, Clang doesn't know `-dumpfullversion`.
+fn reports_version(path: &Path, version: &str) -> bool {
+    ["-dumpfullversion", "-dumpversion"].iter().any(|flag| {
+        Command::new(path).arg(flag).output().is_ok_and(|output| {
+            output.status.success()
+                && names::version_matches(&String::from_utf8_lossy(&output.stdout), version)
+        })
+    })
+}
+
+/// Variables GCC exports to the programs it runs. `collect2` finds `ld` through `COMPILER_PATH`,
+/// `lto-wrapper` needs `COLLECT_GCC` and `COLLECT_GCC_OPTIONS` to run the compiler again, and
+/// `COLLECT_AS_OPTIONS` to pass `-Wa,` options to the assembler of the LTO compile.
+const DRIVER_ENV_VARS: &[&str] = &[
+    "COLLECT_GCC",
+    "COLLECT_LTO_WRAPPER",
+    "COMPILER_PATH",
+    "LIBRARY_PATH",
+    "COLLECT_GCC_OPTIONS",
+    "COLLECT_AS_OPTIONS",
+];
+
 #[derive(Debug, PartialEq, Eq)]
 struct Commands<'a> {
-    build_and_assemble: Vec<&'a str>,
-    link: Option<&'a str>,
+    build_and_assemble: Vec<Step<'a>>,
+    link: Option<Step<'a>>,
+}
+
+/// A command from the dump together with the environment the driver would run it in.
+#[derive(Debug, PartialEq, Eq)]
+struct Step<'a> {
+    command: &'a [u8],
+    /// Values of [`DRIVER_ENV_VARS`] printed before the command, the latest one for each.
+    env: Vec<(&'a str, &'a [u8])>,
+}
+
+impl<'a> Step<'a> {
+    fn program(&self) -> &'a Path {
+        Path::new(OsStr::from_bytes(
+            self.command.split(|b| *b == b' ').next().unwrap(),
+        ))
+    }
+}
+
+fn env_vars<'a>(env: &'a [(&str, &[u8])]) -> impl Iterator<Item = (&'a str, &'a OsStr)> {
+    env.iter()
+        .map(|(name, value)| (*name, OsStr::from_bytes(value)))
 }
 
-fn obtain_whole_command(mut dumped_lines: Lines) -> Result<Commands> {
+fn obtain_whole_command<'a>(
+    mut dumped_lines: impl Iterator<Item = &'a [u8]>,
+) -> Result<Commands<'a>> {
     let first_real_line = loop {
         if let Some(line) = dumped_lines.next() {
-            if !line.trim().is_empty() {
+            if !line.trim_ascii().is_empty() {
                 break line;
             }
         } else {
@@ -160,26 +726,34 @@ fn obtain_whole_command(mut dumped_lines: Lines) -> Result<Commands> {
         }
     };
 
-    if first_real_line.starts_with("clang") {
+    // Possibly with a vendor prefix like `Debian clang version 14.0.6`.
+    if first_real_line
+        .windows(b"clang version".len())
+        .any(|window| window == b"clang version")
+    {
         parse_clang(dumped_lines)
     } else {
         parse_gcc(dumped_lines)
     }
 }
 
-fn parse_clang(dumped_lines: Lines) -> Result<Commands> {
+fn parse_clang<'a>(dumped_lines: impl Iterator<Item = &'a [u8]>) -> Result<Commands<'a>> {
     let mut commands = dumped_lines
         .filter_map(|line| {
-            (line.starts_with(' ') && !line.ends_with("(in-process)"))
-                .then(|| line.trim())
+            (line.starts_with(b" ") && !line.ends_with(b"(in-process)"))
+                .then(|| line.trim_ascii())
                 .filter(|trimmed| !trimmed.is_empty())
         })
+        .map(|command| Step {
+            command,
+            env: Vec::new(),
+        })
         .collect::<Vec<_>>();
 
-    let linker_command = commands.pop_if(|command| {
-        let path = Path::new(command.split(' ').next().unwrap());
+    let linker_command = commands.pop_if(|step| {
         // clang/clang++ binaries perform everything except linking
-        !path
+        !step
+            .program()
             .file_stem()
             .unwrap()
             .to_string_lossy()
@@ -194,25 +768,32 @@ fn parse_clang(dumped_lines: Lines) -> Result<Commands> {
     Ok(commands)
 }
 
-fn parse_gcc(dumped_lines: Lines) -> Result<Commands> {
-    let mut commands = dumped_lines
-        .filter_map(|line| {
-            if line.starts_with(' ') {
-                Some(line.trim()).filter(|s| !s.is_empty())
-            } else if line.contains("error: ") {
-                // GCC exits with 0 when passing `-### -wrong-arg` but shows the error message
-                eprintln!("{line}");
-                exit(1);
-            } else {
-                None
+fn parse_gcc<'a>(dumped_lines: impl Iterator<Item = &'a [u8]>) -> Result<Commands<'a>> {
+    let mut commands = Vec::new();
+    let mut env = Vec::<(&str, &[u8])>::new();
+    for line in dumped_lines {
+        let command = line.trim_ascii();
+        if line.starts_with(b" ") {
+            if !command.is_empty() {
+                commands.push(Step {
+                    command,
+                    env: env.clone(),
+                });
             }
-        })
-        .collect::<Vec<_>>();
+        } else if let Some((name, value)) = DRIVER_ENV_VARS.iter().find_map(|name| {
+            let value = line.strip_prefix(name.as_bytes())?.strip_prefix(b"=")?;
+            Some((*name, value))
+        }) {
+            match env.iter_mut().find(|(set, _)| *set == name) {
+                Some(var) => var.1 = value,
+                None => env.push((name, value)),
+            }
+        }
+    }
 
-    let linker_command = commands.pop_if(|command| {
-        let path = Path::new(command.split(' ').next().unwrap());
+    let linker_command = commands.pop_if(|step| {
         // Collect2 binary is responsible for linking, other binaries compile or assebmle
-        path.file_stem().unwrap() == "collect2"
+        step.program().file_stem().unwrap() == "collect2"
     });
 
     let commands = Commands {
@@ -228,6 +809,16 @@ mod test {
     use super::*;
     use pretty_assertions::assert_eq;
 
+    fn step<'a>(command: &'a str, env: &[(&'a str, &'a str)]) -> Step<'a> {
+        Step {
+            command: command.as_bytes(),
+            env: env
+                .iter()
+                .map(|(name, value)| (*name, value.as_bytes()))
+                .collect(),
+        }
+    }
+
     #[test]
     fn test_parse_clang() {
         let input = r#"
@@ -239,14 +830,19 @@ InstalledDir: /usr/bin
  "/usr/bin/ld" "--hash-style=gnu" "--build-id" "--eh-frame-hdr" "-m" "elf_x86_64" "-pie" "-dynamic-linker" "/lib64/ld-linux-x86-64.so.2" "-o" "a.out" "/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../lib64/Scrt1.o" "/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../lib64/crti.o" "/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1/crtbeginS.o" "-L/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1" "-L/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../lib64" "-L/lib/../lib64" "-L/usr/lib/../lib64" "-L/lib" "-L/usr/lib" "/tmp/hello-5bcb74.o" "-lstdc++" "-lm" "-lgcc_s" "-lgcc" "-lc" "-lgcc_s" "-lgcc" "/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1/crtendS.o" "/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../lib64/crtn.o"
             "#;
         let expected = Commands {
-            build_and_assemble: vec![
+            build_and_assemble: vec![step(
                 r#""/usr/bin/clang++" "-cc1" "-triple" "x86_64-pc-linux-gnu" "-emit-obj" "-dumpdir" "a-" "-disable-free" "-clear-ast-before-backend" "-disable-llvm-verifier" "-discard-value-names" "-main-file-name" "hello.cpp" "-mrelocation-model" "pic" "-pic-level" "2" "-pic-is-pie" "-mframe-pointer=all" "-fmath-errno" "-ffp-contract=on" "-fno-rounding-math" "-mconstructor-aliases" "-funwind-tables=2" "-target-cpu" "x86-64" "-tune-cpu" "generic" "-debugger-tuning=gdb" "-fdebug-compilation-dir=/tmp" "-fcoverage-compilation-dir=/tmp" "-resource-dir" "/usr/lib/clang/19" "-internal-isystem" "/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../include/c++/14.2.1" "-internal-isystem" "/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../include/c++/14.2.1/x86_64-pc-linux-gnu" "-internal-isystem" "/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../include/c++/14.2.1/backward" "-internal-isystem" "/usr/lib/clang/19/include" "-internal-isystem" "/usr/local/include" "-internal-isystem" "/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../x86_64-pc-linux-gnu/include" "-internal-externc-isystem" "/include" "-internal-externc-isystem" "/usr/include" "-fdeprecated-macro" "-ferror-limit" "19" "-stack-protector" "2" "-fgnuc-version=4.2.1" "-fskip-odr-check-in-gmf" "-fcxx-exceptions" "-fexceptions" "-faddrsig" "-D__GCC_HAVE_DWARF2_CFI_ASM=1" "-o" "/tmp/hello-5bcb74.o" "-x" "c++" "hello.cpp""#,
-            ],
-            link: Some(
+                &[],
+            )],
+            link: Some(step(
                 r#""/usr/bin/ld" "--hash-style=gnu" "--build-id" "--eh-frame-hdr" "-m" "elf_x86_64" "-pie" "-dynamic-linker" "/lib64/ld-linux-x86-64.so.2" "-o" "a.out" "/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../lib64/Scrt1.o" "/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../lib64/crti.o" "/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1/crtbeginS.o" "-L/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1" "-L/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../lib64" "-L/lib/../lib64" "-L/usr/lib/../lib64" "-L/lib" "-L/usr/lib" "/tmp/hello-5bcb74.o" "-lstdc++" "-lm" "-lgcc_s" "-lgcc" "-lc" "-lgcc_s" "-lgcc" "/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1/crtendS.o" "/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../lib64/crtn.o""#,
-            ),
+                &[],
+            )),
         };
-        assert_eq!(expected, obtain_whole_command(input.lines()).unwrap());
+        assert_eq!(
+            expected,
+            obtain_whole_command(probe::lines(input.as_bytes())).unwrap()
+        );
     }
 
     #[test]
@@ -260,12 +856,16 @@ InstalledDir: /usr/bin
  "/usr/bin/clang++" "-cc1" "-triple" "x86_64-pc-linux-gnu" "-emit-obj" "-disable-free" "-clear-ast-before-backend" "-disable-llvm-verifier" "-discard-value-names" "-main-file-name" "hello.cpp" "-mrelocation-model" "pic" "-pic-level" "2" "-pic-is-pie" "-mframe-pointer=all" "-fmath-errno" "-ffp-contract=on" "-fno-rounding-math" "-mconstructor-aliases" "-funwind-tables=2" "-target-cpu" "x86-64" "-tune-cpu" "generic" "-debugger-tuning=gdb" "-fdebug-compilation-dir=/tmp" "-fcoverage-compilation-dir=/tmp" "-resource-dir" "/usr/lib/clang/19" "-internal-isystem" "/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../include/c++/14.2.1" "-internal-isystem" "/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../include/c++/14.2.1/x86_64-pc-linux-gnu" "-internal-isystem" "/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../include/c++/14.2.1/backward" "-internal-isystem" "/usr/lib/clang/19/include" "-internal-isystem" "/usr/local/include" "-internal-isystem" "/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../x86_64-pc-linux-gnu/include" "-internal-externc-isystem" "/include" "-internal-externc-isystem" "/usr/include" "-fdeprecated-macro" "-ferror-limit" "19" "-stack-protector" "2" "-fgnuc-version=4.2.1" "-fskip-odr-check-in-gmf" "-fcxx-exceptions" "-fexceptions" "-faddrsig" "-D__GCC_HAVE_DWARF2_CFI_ASM=1" "-o" "hello.o" "-x" "c++" "hello.cpp"
             "#;
         let expected = Commands {
-            build_and_assemble: vec![
+            build_and_assemble: vec![step(
                 r#""/usr/bin/clang++" "-cc1" "-triple" "x86_64-pc-linux-gnu" "-emit-obj" "-disable-free" "-clear-ast-before-backend" "-disable-llvm-verifier" "-discard-value-names" "-main-file-name" "hello.cpp" "-mrelocation-model" "pic" "-pic-level" "2" "-pic-is-pie" "-mframe-pointer=all" "-fmath-errno" "-ffp-contract=on" "-fno-rounding-math" "-mconstructor-aliases" "-funwind-tables=2" "-target-cpu" "x86-64" "-tune-cpu" "generic" "-debugger-tuning=gdb" "-fdebug-compilation-dir=/tmp" "-fcoverage-compilation-dir=/tmp" "-resource-dir" "/usr/lib/clang/19" "-internal-isystem" "/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../include/c++/14.2.1" "-internal-isystem" "/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../include/c++/14.2.1/x86_64-pc-linux-gnu" "-internal-isystem" "/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../include/c++/14.2.1/backward" "-internal-isystem" "/usr/lib/clang/19/include" "-internal-isystem" "/usr/local/include" "-internal-isystem" "/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../x86_64-pc-linux-gnu/include" "-internal-externc-isystem" "/include" "-internal-externc-isystem" "/usr/include" "-fdeprecated-macro" "-ferror-limit" "19" "-stack-protector" "2" "-fgnuc-version=4.2.1" "-fskip-odr-check-in-gmf" "-fcxx-exceptions" "-fexceptions" "-faddrsig" "-D__GCC_HAVE_DWARF2_CFI_ASM=1" "-o" "hello.o" "-x" "c++" "hello.cpp""#,
-            ],
+                &[],
+            )],
             link: None,
         };
-        assert_eq!(expected, obtain_whole_command(input.lines()).unwrap());
+        assert_eq!(
+            expected,
+            obtain_whole_command(probe::lines(input.as_bytes())).unwrap()
+        );
     }
 
     #[test]
@@ -279,12 +879,16 @@ InstalledDir: /usr/bin
     "/usr/bin/clang-19" "-cc1" "-triple" "x86_64-pc-linux-gnu" "-E" "-disable-free" "-clear-ast-before-backend" "-disable-llvm-verifier" "-discard-value-names" "-main-file-name" "-" "-mrelocation-model" "pic" "-pic-level" "2" "-pic-is-pie" "-mframe-pointer=all" "-fmath-errno" "-ffp-contract=on" "-fno-rounding-math" "-mconstructor-aliases" "-funwind-tables=2" "-target-cpu" "x86-64" "-tune-cpu" "generic" "-debugger-tuning=gdb" "-fdebug-compilation-dir=/home/mateusz/Projects/rust" "-fcoverage-compilation-dir=/home/mateusz/Projects/rust" "-resource-dir" "/usr/lib/clang/19" "-internal-isystem" "/usr/lib/clang/19/include" "-internal-isystem" "/usr/local/include" "-internal-isystem" "/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../x86_64-pc-linux-gnu/include" "-internal-externc-isystem" "/include" "-internal-externc-isystem" "/usr/include" "-ferror-limit" "19" "-stack-protector" "2" "-fgnuc-version=4.2.1" "-fskip-odr-check-in-gmf" "-fcolor-diagnostics" "-faddrsig" "-D__GCC_HAVE_DWARF2_CFI_ASM=1" "-o" "-" "-x" "c" "-"
         "#;
         let expected = Commands {
-            build_and_assemble: vec![
+            build_and_assemble: vec![step(
                 r#""/usr/bin/clang-19" "-cc1" "-triple" "x86_64-pc-linux-gnu" "-E" "-disable-free" "-clear-ast-before-backend" "-disable-llvm-verifier" "-discard-value-names" "-main-file-name" "-" "-mrelocation-model" "pic" "-pic-level" "2" "-pic-is-pie" "-mframe-pointer=all" "-fmath-errno" "-ffp-contract=on" "-fno-rounding-math" "-mconstructor-aliases" "-funwind-tables=2" "-target-cpu" "x86-64" "-tune-cpu" "generic" "-debugger-tuning=gdb" "-fdebug-compilation-dir=/home/mateusz/Projects/rust" "-fcoverage-compilation-dir=/home/mateusz/Projects/rust" "-resource-dir" "/usr/lib/clang/19" "-internal-isystem" "/usr/lib/clang/19/include" "-internal-isystem" "/usr/local/include" "-internal-isystem" "/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../x86_64-pc-linux-gnu/include" "-internal-externc-isystem" "/include" "-internal-externc-isystem" "/usr/include" "-ferror-limit" "19" "-stack-protector" "2" "-fgnuc-version=4.2.1" "-fskip-odr-check-in-gmf" "-fcolor-diagnostics" "-faddrsig" "-D__GCC_HAVE_DWARF2_CFI_ASM=1" "-o" "-" "-x" "c" "-""#,
-            ],
+                &[],
+            )],
             link: None,
         };
-        assert_eq!(expected, obtain_whole_command(input.lines()).unwrap());
+        assert_eq!(
+            expected,
+            obtain_whole_command(probe::lines(input.as_bytes())).unwrap()
+        );
     }
 
     #[test]
@@ -298,12 +902,16 @@ InstalledDir: /usr/bin
  "/usr/bin/clang++" "-cc1" "-triple" "x86_64-pc-linux-gnu" "-S" "-disable-free" "-clear-ast-before-backend" "-disable-llvm-verifier" "-discard-value-names" "-main-file-name" "hello.cpp" "-mrelocation-model" "pic" "-pic-level" "2" "-pic-is-pie" "-mframe-pointer=all" "-fmath-errno" "-ffp-contract=on" "-fno-rounding-math" "-mconstructor-aliases" "-funwind-tables=2" "-target-cpu" "x86-64" "-tune-cpu" "generic" "-debugger-tuning=gdb" "-fdebug-compilation-dir=/tmp" "-fcoverage-compilation-dir=/tmp" "-resource-dir" "/usr/lib/clang/19" "-internal-isystem" "/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../include/c++/14.2.1" "-internal-isystem" "/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../include/c++/14.2.1/x86_64-pc-linux-gnu" "-internal-isystem" "/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../include/c++/14.2.1/backward" "-internal-isystem" "/usr/lib/clang/19/include" "-internal-isystem" "/usr/local/include" "-internal-isystem" "/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../x86_64-pc-linux-gnu/include" "-internal-externc-isystem" "/include" "-internal-externc-isystem" "/usr/include" "-fdeprecated-macro" "-ferror-limit" "19" "-stack-protector" "2" "-fgnuc-version=4.2.1" "-fskip-odr-check-in-gmf" "-fcxx-exceptions" "-fexceptions" "-fcolor-diagnostics" "-faddrsig" "-D__GCC_HAVE_DWARF2_CFI_ASM=1" "-o" "hello.s" "-x" "c++" "hello.cpp"
                 "#;
         let expected = Commands {
-            build_and_assemble: vec![
+            build_and_assemble: vec![step(
                 r#""/usr/bin/clang++" "-cc1" "-triple" "x86_64-pc-linux-gnu" "-S" "-disable-free" "-clear-ast-before-backend" "-disable-llvm-verifier" "-discard-value-names" "-main-file-name" "hello.cpp" "-mrelocation-model" "pic" "-pic-level" "2" "-pic-is-pie" "-mframe-pointer=all" "-fmath-errno" "-ffp-contract=on" "-fno-rounding-math" "-mconstructor-aliases" "-funwind-tables=2" "-target-cpu" "x86-64" "-tune-cpu" "generic" "-debugger-tuning=gdb" "-fdebug-compilation-dir=/tmp" "-fcoverage-compilation-dir=/tmp" "-resource-dir" "/usr/lib/clang/19" "-internal-isystem" "/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../include/c++/14.2.1" "-internal-isystem" "/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../include/c++/14.2.1/x86_64-pc-linux-gnu" "-internal-isystem" "/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../include/c++/14.2.1/backward" "-internal-isystem" "/usr/lib/clang/19/include" "-internal-isystem" "/usr/local/include" "-internal-isystem" "/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../x86_64-pc-linux-gnu/include" "-internal-externc-isystem" "/include" "-internal-externc-isystem" "/usr/include" "-fdeprecated-macro" "-ferror-limit" "19" "-stack-protector" "2" "-fgnuc-version=4.2.1" "-fskip-odr-check-in-gmf" "-fcxx-exceptions" "-fexceptions" "-fcolor-diagnostics" "-faddrsig" "-D__GCC_HAVE_DWARF2_CFI_ASM=1" "-o" "hello.s" "-x" "c++" "hello.cpp""#,
-            ],
+                &[],
+            )],
             link: None,
         };
-        assert_eq!(expected, obtain_whole_command(input.lines()).unwrap());
+        assert_eq!(
+            expected,
+            obtain_whole_command(probe::lines(input.as_bytes())).unwrap()
+        );
     }
 
     #[test]
@@ -317,12 +925,16 @@ InstalledDir: /usr/bin
  "/usr/bin/clang++" "-cc1as" "-triple" "x86_64-pc-linux-gnu" "-filetype" "obj" "-main-file-name" "hello.s" "-target-cpu" "x86-64" "-fdebug-compilation-dir=/tmp" "-dwarf-debug-producer" "clang version 19.1.7" "-dwarf-version=5" "-mrelocation-model" "pic" "-o" "hello.o" "hello.s"
                 "#;
         let expected = Commands {
-            build_and_assemble: vec![
+            build_and_assemble: vec![step(
                 r#""/usr/bin/clang++" "-cc1as" "-triple" "x86_64-pc-linux-gnu" "-filetype" "obj" "-main-file-name" "hello.s" "-target-cpu" "x86-64" "-fdebug-compilation-dir=/tmp" "-dwarf-debug-producer" "clang version 19.1.7" "-dwarf-version=5" "-mrelocation-model" "pic" "-o" "hello.o" "hello.s""#,
-            ],
+                &[],
+            )],
             link: None,
         };
-        assert_eq!(expected, obtain_whole_command(input.lines()).unwrap());
+        assert_eq!(
+            expected,
+            obtain_whole_command(probe::lines(input.as_bytes())).unwrap()
+        );
     }
 
     #[test]
@@ -337,11 +949,15 @@ InstalledDir: /usr/bin
                     "#;
         let expected = Commands {
             build_and_assemble: vec![],
-            link: Some(
+            link: Some(step(
                 r#""/usr/bin/ld" "--hash-style=gnu" "--build-id" "--eh-frame-hdr" "-m" "elf_x86_64" "-pie" "-dynamic-linker" "/lib64/ld-linux-x86-64.so.2" "-o" "a.out" "/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../lib64/Scrt1.o" "/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../lib64/crti.o" "/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1/crtbeginS.o" "-L/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1" "-L/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../lib64" "-L/lib/../lib64" "-L/usr/lib/../lib64" "-L/lib" "-L/usr/lib" "hello.o" "-lstdc++" "-lm" "-lgcc_s" "-lgcc" "-lc" "-lgcc_s" "-lgcc" "/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1/crtendS.o" "/usr/bin/../lib64/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../lib64/crtn.o""#,
-            ),
+                &[],
+            )),
         };
-        assert_eq!(expected, obtain_whole_command(input.lines()).unwrap());
+        assert_eq!(
+            expected,
+            obtain_whole_command(probe::lines(input.as_bytes())).unwrap()
+        );
     }
 
     #[test]
@@ -367,14 +983,95 @@ COLLECT_GCC_OPTIONS='-shared-libgcc' '-mtune=generic' '-march=x86-64' '-dumpdir'
             "#;
         let expected = Commands {
             build_and_assemble: vec![
-                r#"/usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/cc1plus -quiet -D_GNU_SOURCE hello.cpp -quiet -dumpdir a- -dumpbase hello.cpp -dumpbase-ext .cpp "-mtune=generic" "-march=x86-64" -o /tmp/ccxGHCn4.s"#,
-                r#"as --64 -o /tmp/ccql7Oad.o /tmp/ccxGHCn4.s"#,
+                step(
+                    r#"/usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/cc1plus -quiet -D_GNU_SOURCE hello.cpp -quiet -dumpdir a- -dumpbase hello.cpp -dumpbase-ext .cpp "-mtune=generic" "-march=x86-64" -o /tmp/ccxGHCn4.s"#,
+                    &[
+                        ("COLLECT_GCC", "g++"),
+                        (
+                            "COLLECT_LTO_WRAPPER",
+                            "/usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/lto-wrapper",
+                        ),
+                        (
+                            "COLLECT_GCC_OPTIONS",
+                            "'-shared-libgcc' '-mtune=generic' '-march=x86-64' '-dumpdir' 'a-'",
+                        ),
+                    ],
+                ),
+                step(
+                    r#"as --64 -o /tmp/ccql7Oad.o /tmp/ccxGHCn4.s"#,
+                    &[
+                        ("COLLECT_GCC", "g++"),
+                        (
+                            "COLLECT_LTO_WRAPPER",
+                            "/usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/lto-wrapper",
+                        ),
+                        (
+                            "COLLECT_GCC_OPTIONS",
+                            "'-shared-libgcc' '-mtune=generic' '-march=x86-64' '-dumpdir' 'a-'",
+                        ),
+                    ],
+                ),
             ],
-            link: Some(
+            link: Some(step(
                 r#"/usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/collect2 -plugin /usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/liblto_plugin.so "-plugin-opt=/usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/lto-wrapper" "-plugin-opt=-fresolution=/tmp/ccIkCFvS.res" "-plugin-opt=-pass-through=-lgcc_s" "-plugin-opt=-pass-through=-lgcc" "-plugin-opt=-pass-through=-lc" "-plugin-opt=-pass-through=-lgcc_s" "-plugin-opt=-pass-through=-lgcc" --build-id --eh-frame-hdr "--hash-style=gnu" -m elf_x86_64 -dynamic-linker /lib64/ld-linux-x86-64.so.2 -pie /usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../lib/Scrt1.o /usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../lib/crti.o /usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/crtbeginS.o -L/usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1 -L/usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../lib -L/lib/../lib -L/usr/lib/../lib -L/usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/../../.. /tmp/ccql7Oad.o "-lstdc++" -lm -lgcc_s -lgcc -lc -lgcc_s -lgcc /usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/crtendS.o /usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../lib/crtn.o"#,
-            ),
+                &[
+                    ("COLLECT_GCC", "g++"),
+                    (
+                        "COLLECT_LTO_WRAPPER",
+                        "/usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/lto-wrapper",
+                    ),
+                    (
+                        "COLLECT_GCC_OPTIONS",
+                        "'-shared-libgcc' '-mtune=generic' '-march=x86-64' '-dumpdir' 'a.'",
+                    ),
+                    (
+                        "COMPILER_PATH",
+                        "/usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/:/usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/:/usr/lib/gcc/x86_64-pc-linux-gnu/:/usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/:/usr/lib/gcc/x86_64-pc-linux-gnu/",
+                    ),
+                    (
+                        "LIBRARY_PATH",
+                        "/usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/:/usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../lib/:/lib/../lib/:/usr/lib/../lib/:/usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/../../../:/lib/:/usr/lib/",
+                    ),
+                ],
+            )),
+        };
+        assert_eq!(
+            expected,
+            obtain_whole_command(probe::lines(input.as_bytes())).unwrap()
+        );
+    }
+
+    #[test]
+    fn parse_gcc_assembler_options() {
+        let input = r#"Using built-in specs.
+COLLECT_AS_OPTIONS='--noexecstack'
+COLLECT_GCC=gcc
+gcc version 12.2.0 (Debian 12.2.0-14+deb12u1)
+COLLECT_GCC_OPTIONS='-o' 'm' '-flto'
+ as --64 --noexecstack -o /tmp/cc2rh07X.o /tmp/ccpNhwaN.s
+COLLECT_GCC_OPTIONS='-o' 'm' '-flto' '-dumpdir' 'm.'
+ /usr/lib/gcc/x86_64-linux-gnu/12/collect2 -o m /tmp/cc2rh07X.o
+"#;
+        let env = |options| {
+            vec![
+                ("COLLECT_AS_OPTIONS", "'--noexecstack'"),
+                ("COLLECT_GCC", "gcc"),
+                ("COLLECT_GCC_OPTIONS", options),
+            ]
         };
-        assert_eq!(expected, obtain_whole_command(input.lines()).unwrap());
+        assert_eq!(
+            Commands {
+                build_and_assemble: vec![step(
+                    "as --64 --noexecstack -o /tmp/cc2rh07X.o /tmp/ccpNhwaN.s",
+                    &env("'-o' 'm' '-flto'"),
+                )],
+                link: Some(step(
+                    "/usr/lib/gcc/x86_64-linux-gnu/12/collect2 -o m /tmp/cc2rh07X.o",
+                    &env("'-o' 'm' '-flto' '-dumpdir' 'm.'"),
+                )),
+            },
+            obtain_whole_command(probe::lines(input.as_bytes())).unwrap()
+        );
     }
 
     #[test]
@@ -397,12 +1094,33 @@ COLLECT_GCC_OPTIONS='-c' '-shared-libgcc' '-mtune=generic' '-march=x86-64'
             "#;
         let expected = Commands {
             build_and_assemble: vec![
-                r#"/usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/cc1plus -quiet -D_GNU_SOURCE hello.cpp -quiet -dumpbase hello.cpp -dumpbase-ext .cpp "-mtune=generic" "-march=x86-64" -o /tmp/cc47fLtr.s"#,
-                r#"as --64 -o hello.o /tmp/cc47fLtr.s"#,
+                step(
+                    r#"/usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/cc1plus -quiet -D_GNU_SOURCE hello.cpp -quiet -dumpbase hello.cpp -dumpbase-ext .cpp "-mtune=generic" "-march=x86-64" -o /tmp/cc47fLtr.s"#,
+                    &[
+                        ("COLLECT_GCC", "g++"),
+                        (
+                            "COLLECT_GCC_OPTIONS",
+                            "'-c' '-shared-libgcc' '-mtune=generic' '-march=x86-64'",
+                        ),
+                    ],
+                ),
+                step(
+                    r#"as --64 -o hello.o /tmp/cc47fLtr.s"#,
+                    &[
+                        ("COLLECT_GCC", "g++"),
+                        (
+                            "COLLECT_GCC_OPTIONS",
+                            "'-c' '-shared-libgcc' '-mtune=generic' '-march=x86-64'",
+                        ),
+                    ],
+                ),
             ],
             link: None,
         };
-        assert_eq!(expected, obtain_whole_command(input.lines()).unwrap());
+        assert_eq!(
+            expected,
+            obtain_whole_command(probe::lines(input.as_bytes())).unwrap()
+        );
     }
 
     #[test]
@@ -422,12 +1140,22 @@ LIBRARY_PATH=/usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/:/usr/lib/gcc/x86_64-pc-lin
 COLLECT_GCC_OPTIONS='-E' '-mtune=generic' '-march=x86-64'
         "#;
         let expected = Commands {
-            build_and_assemble: vec![
+            build_and_assemble: vec![step(
                 r#"/usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/cc1 -E -quiet - "-mtune=generic" "-march=x86-64" -dumpbase -"#,
-            ],
+                &[
+                    ("COLLECT_GCC", "gcc"),
+                    (
+                        "COLLECT_GCC_OPTIONS",
+                        "'-E' '-mtune=generic' '-march=x86-64'",
+                    ),
+                ],
+            )],
             link: None,
         };
-        assert_eq!(expected, obtain_whole_command(input.lines()).unwrap());
+        assert_eq!(
+            expected,
+            obtain_whole_command(probe::lines(input.as_bytes())).unwrap()
+        );
     }
 
     #[test]
@@ -447,12 +1175,22 @@ LIBRARY_PATH=/usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/:/usr/lib/gcc/x86_64-pc-lin
 COLLECT_GCC_OPTIONS='-S' '-shared-libgcc' '-mtune=generic' '-march=x86-64'
             "#;
         let expected = Commands {
-            build_and_assemble: vec![
+            build_and_assemble: vec![step(
                 r#"/usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/cc1plus -quiet -D_GNU_SOURCE hello.cpp -quiet -dumpbase hello.cpp -dumpbase-ext .cpp "-mtune=generic" "-march=x86-64" -o hello.s"#,
-            ],
+                &[
+                    ("COLLECT_GCC", "g++"),
+                    (
+                        "COLLECT_GCC_OPTIONS",
+                        "'-S' '-shared-libgcc' '-mtune=generic' '-march=x86-64'",
+                    ),
+                ],
+            )],
             link: None,
         };
-        assert_eq!(expected, obtain_whole_command(input.lines()).unwrap());
+        assert_eq!(
+            expected,
+            obtain_whole_command(probe::lines(input.as_bytes())).unwrap()
+        );
     }
 
     #[test]
@@ -472,10 +1210,22 @@ LIBRARY_PATH=/usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/:/usr/lib/gcc/x86_64-pc-lin
 COLLECT_GCC_OPTIONS='-c' '-shared-libgcc' '-mtune=generic' '-march=x86-64'
             "#;
         let expected = Commands {
-            build_and_assemble: vec![r#"as --64 -o hello.o hello.s"#],
+            build_and_assemble: vec![step(
+                r#"as --64 -o hello.o hello.s"#,
+                &[
+                    ("COLLECT_GCC", "g++"),
+                    (
+                        "COLLECT_GCC_OPTIONS",
+                        "'-c' '-shared-libgcc' '-mtune=generic' '-march=x86-64'",
+                    ),
+                ],
+            )],
             link: None,
         };
-        assert_eq!(expected, obtain_whole_command(input.lines()).unwrap());
+        assert_eq!(
+            expected,
+            obtain_whole_command(probe::lines(input.as_bytes())).unwrap()
+        );
     }
 
     #[test]
@@ -497,10 +1247,161 @@ COLLECT_GCC_OPTIONS='-shared-libgcc' '-mtune=generic' '-march=x86-64' '-dumpdir'
             "#;
         let expected = Commands {
             build_and_assemble: vec![],
-            link: Some(
+            link: Some(step(
                 r#"/usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/collect2 -plugin /usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/liblto_plugin.so "-plugin-opt=/usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/lto-wrapper" "-plugin-opt=-fresolution=/tmp/ccluTT6J.res" "-plugin-opt=-pass-through=-lgcc_s" "-plugin-opt=-pass-through=-lgcc" "-plugin-opt=-pass-through=-lc" "-plugin-opt=-pass-through=-lgcc_s" "-plugin-opt=-pass-through=-lgcc" --build-id --eh-frame-hdr "--hash-style=gnu" -m elf_x86_64 -dynamic-linker /lib64/ld-linux-x86-64.so.2 -pie /usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../lib/Scrt1.o /usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../lib/crti.o /usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/crtbeginS.o -L/usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1 -L/usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../lib -L/lib/../lib -L/usr/lib/../lib -L/usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/../../.. hello.o "-lstdc++" -lm -lgcc_s -lgcc -lc -lgcc_s -lgcc /usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/crtendS.o /usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../lib/crtn.o"#,
-            ),
+                &[
+                    ("COLLECT_GCC", "g++"),
+                    (
+                        "COLLECT_LTO_WRAPPER",
+                        "/usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/lto-wrapper",
+                    ),
+                    (
+                        "COMPILER_PATH",
+                        "/usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/:/usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/:/usr/lib/gcc/x86_64-pc-linux-gnu/:/usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/:/usr/lib/gcc/x86_64-pc-linux-gnu/",
+                    ),
+                    (
+                        "LIBRARY_PATH",
+                        "/usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/:/usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/../../../../lib/:/lib/../lib/:/usr/lib/../lib/:/usr/lib/gcc/x86_64-pc-linux-gnu/14.2.1/../../../:/lib/:/usr/lib/",
+                    ),
+                    (
+                        "COLLECT_GCC_OPTIONS",
+                        "'-shared-libgcc' '-mtune=generic' '-march=x86-64' '-dumpdir' 'a.'",
+                    ),
+                ],
+            )),
+        };
+        assert_eq!(
+            expected,
+            obtain_whole_command(probe::lines(input.as_bytes())).unwrap()
+        );
+    }
+
+    #[test]
+    fn compiler_wrapper_orderings() {
+        let dir =
+            std::env::temp_dir().join(format!("wild-proxy-wrapper-test-{}", std::process::id()));
+        for sub in ["exe", "fakes", "ccache", "bin"] {
+            std::fs::create_dir_all(dir.join(sub)).unwrap();
+        }
+        let real_exe = dir.join("exe/wild-proxy");
+        for path in [&real_exe, &dir.join("bin/gcc"), &dir.join("bin/ccache")] {
+            std::fs::write(path, "").unwrap();
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        std::os::unix::fs::symlink("../exe/wild-proxy", dir.join("fakes/gcc")).unwrap();
+        std::os::unix::fs::symlink("../bin/ccache", dir.join("ccache/gcc")).unwrap();
+        let real_gcc = dir.join("bin/gcc");
+        let ccache = dir.join("bin/ccache").canonicalize().unwrap();
+
+        let resolve = |path_dirs: &[&str], driver_argv: &[&str]| {
+            let paths = std::env::join_paths(path_dirs.iter().map(|sub| dir.join(sub))).unwrap();
+            let driver_argv = driver_argv.iter().map(OsString::from).collect::<Vec<_>>();
+            let (next_executable, args) =
+                resolve_compiler_in(&driver_argv, &[], &paths, &real_exe).unwrap();
+            (next_executable.path, next_executable.wrapper, args.to_vec())
         };
-        assert_eq!(expected, obtain_whole_command(input.lines()).unwrap());
+
+        // Masquerade directory after the impostors: compile-only invocations go through ccache.
+        assert_eq!(
+            (
+                real_gcc.clone(),
+                Some(ccache.clone()),
+                vec![OsString::from("foo.c")]
+            ),
+            resolve(&["fakes", "ccache", "bin"], &["gcc", "foo.c"])
+        );
+        // Before the impostors: ccache has already run and called us.
+        assert_eq!(
+            (real_gcc.clone(), None, vec![OsString::from("foo.c")]),
+            resolve(&["ccache", "fakes", "bin"], &["gcc", "foo.c"])
+        );
+        // After the real compiler: never used.
+        assert_eq!(
+            (real_gcc.clone(), None, vec![OsString::from("foo.c")]),
+            resolve(&["fakes", "bin", "ccache"], &["gcc", "foo.c"])
+        );
+        // Launcher chain `wild-proxy ccache gcc`.
+        assert_eq!(
+            (
+                real_gcc.clone(),
+                Some(dir.join("bin/ccache")),
+                vec![OsString::from("foo.c")]
+            ),
+            resolve(&["fakes", "bin"], &["ccache", "gcc", "foo.c"])
+        );
+        // Launcher chain `ccache wild-proxy gcc`, ccache is the parent process.
+        assert_eq!(
+            (real_gcc, None, vec![OsString::from("foo.c")]),
+            resolve(&["bin"], &["gcc", "foo.c"])
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn dry_run_shows_wild_link() {
+        let input = r#"Using built-in specs.
+Target: x86_64-linux-gnu
+gcc version 12.2.0 (Debian 12.2.0-14+deb12u1)
+COLLECT_GCC_OPTIONS='-o' 'hello' '-mtune=generic' '-march=x86-64'
+ /usr/lib/gcc/x86_64-linux-gnu/12/cc1 -quiet hello.c -o /tmp/ccbW8f3a.s
+COLLECT_GCC_OPTIONS='-o' 'hello' '-mtune=generic' '-march=x86-64'
+ as --64 -o /tmp/ccHn0GQc.o /tmp/ccbW8f3a.s
+COLLECT_GCC_OPTIONS='-o' 'hello' '-mtune=generic' '-march=x86-64'
+ /usr/lib/gcc/x86_64-linux-gnu/12/collect2 -m elf_x86_64 -o hello "-plugin-opt=-pass-through=-lc" /tmp/ccHn0GQc.o -lc
+COLLECT_GCC_OPTIONS='-o' 'hello' '-mtune=generic' '-march=x86-64'
+"#;
+        let commands = obtain_whole_command(probe::lines(input.as_bytes())).unwrap();
+        let expected = input.replace(
+            r#" /usr/lib/gcc/x86_64-linux-gnu/12/collect2 -m elf_x86_64 -o hello "-plugin-opt=-pass-through=-lc" /tmp/ccHn0GQc.o -lc"#,
+            " wild -m elf_x86_64 -o hello '-plugin-opt=-pass-through=-lc' /tmp/ccHn0GQc.o -lc",
+        );
+        assert_eq!(
+            expected,
+            String::from_utf8(
+                dry_run_dump(
+                    input.as_bytes(),
+                    commands.link.map(|link| link.command),
+                    &Rules::default(),
+                    Path::new("gcc")
+                )
+                .unwrap()
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn non_utf8_paths_in_dump() {
+        let input = b"Using built-in specs.
+COLLECT_GCC_OPTIONS='-o' 'caf\xe9' '-mtune=generic' '-march=x86-64'
+ /usr/lib/gcc/x86_64-linux-gnu/12/cc1 -quiet caf\xe9.c -o /tmp/ccbW8f3a.s
+ /usr/lib/gcc/x86_64-linux-gnu/12/collect2 -m elf_x86_64 -o caf\xe9 \"/tmp/\xe9t\xe9/ccHn0GQc.o\" -lc
+";
+        let commands = obtain_whole_command(probe::lines(input)).unwrap();
+        assert_eq!(
+            vec![
+                b"/usr/lib/gcc/x86_64-linux-gnu/12/cc1 -quiet caf\xe9.c -o /tmp/ccbW8f3a.s"
+                    .as_slice()
+            ],
+            commands
+                .build_and_assemble
+                .iter()
+                .map(|step| step.command)
+                .collect::<Vec<_>>()
+        );
+        let link_args = shell::split(commands.link.unwrap().command).unwrap();
+        assert_eq!(
+            vec![
+                OsStr::new("/usr/lib/gcc/x86_64-linux-gnu/12/collect2"),
+                OsStr::new("-m"),
+                OsStr::new("elf_x86_64"),
+                OsStr::new("-o"),
+                OsStr::from_bytes(b"caf\xe9"),
+                OsStr::from_bytes(b"/tmp/\xe9t\xe9/ccHn0GQc.o"),
+                OsStr::new("-lc"),
+            ],
+            link_args
+        );
     }
 }
@@ -0,0 +1,304 @@
+//! Keys for caching links, when `WILD_PROXY_LINK_CACHE` names a directory. Links are keyed by
+//! Wild's arguments and the contents of every input and script, including libraries found through
+//! `-L`/`-l` and files referenced by linker scripts like glibc's `libc.so`. The least recently used
+//! entries are evicted once the directory grows past `WILD_PROXY_LINK_CACHE_SIZE`.
+
+use crate::{
+    link_plan::{LinkArg, LinkInputKind, LinkPlan},
+    output_cache::add,
+};
+use anyhow::{Result, bail};
+use std::{
+    ffi::{OsStr, OsString},
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+pub(crate) const CACHE_DIR_ENV: &str = "WILD_PROXY_LINK_CACHE";
+pub(crate) const CACHE_SIZE_ENV: &str = "WILD_PROXY_LINK_CACHE_SIZE";
+
+/// Options that write files besides the output, links using them aren't cached.
+const SIDE_OUTPUT_OPTIONS: &[&str] = &[
+    "-M",
+    "--print-map",
+    "-Map",
+    "--Map",
+    "--dependency-file",
+    "--out-implib",
+    "--write-gc-stats",
+];
+
+/// Names the file GCC's LTO plugin writes symbol resolutions to.
+const RESOLUTION_OPTION: &str = "-fresolution=";
+
+/// Options naming linker scripts and other files whose contents change the output.
+const SCRIPT_OPTIONS: &[&str] = &[
+    "-T",
+    "--script",
+    "--version-script",
+    "--dynamic-list",
+    "-R",
+    "--just-symbols",
+    "--retain-symbols-file",
+    "--export-dynamic-symbol-list",
+    "--symbol-ordering-file",
+    "--section-ordering-file",
+];
+
+/// How deep linker scripts referencing other scripts are followed.
+const MAX_SCRIPT_DEPTH: usize = 4;
+
+/// Returns the key for linking `plan` with `wild_args`, or `None` if the link can't be cached.
+pub(crate) fn key(plan: &LinkPlan, wild_args: &[OsString]) -> Option<String> {
+    if wild_args.iter().any(|arg| {
+        let option = arg.as_bytes().split(|&byte| byte == b'=').next();
+        option.is_some_and(|option| {
+            SIDE_OUTPUT_OPTIONS
+                .iter()
+                .any(|side| side.as_bytes() == option)
+        })
+    }) {
+        return None;
+    }
+
+    let mut command = vec![plan.linker.clone().into_os_string()];
+    command.extend_from_slice(wild_args);
+    let wild_plan = LinkPlan::parse(&command).ok()?;
+
+    let mut hasher = blake3::Hasher::new();
+    add(
+        &mut hasher,
+        concat!("wild-proxy ", env!("CARGO_PKG_VERSION")).as_bytes(),
+    );
+    add(&mut hasher, plan.linker.as_os_str().as_bytes());
+    // Where the output goes doesn't change it, so it isn't part of the key.
+    for value in [
+        wild_plan.emulation.as_deref(),
+        wild_plan.dynamic_linker.as_deref().map(Path::as_os_str),
+        wild_plan.plugin.as_deref().map(Path::as_os_str),
+    ] {
+        add(&mut hasher, value.unwrap_or_default().as_bytes());
+    }
+    for dir in &wild_plan.search_paths {
+        add(&mut hasher, dir.as_os_str().as_bytes());
+    }
+    for option in &wild_plan.plugin_options {
+        // GCC names the LTO plugin's resolution file after a new temporary file each time.
+        if option.as_bytes().starts_with(RESOLUTION_OPTION.as_bytes()) {
+            add(&mut hasher, RESOLUTION_OPTION.as_bytes());
+        } else {
+            add(&mut hasher, option.as_bytes());
+        }
+    }
+    for arg in &wild_plan.args {
+        match arg {
+            LinkArg::Flag(flag) => {
+                // Scripts are keyed by their contents, option values like `-soname` by their bytes.
+                for value in flag {
+                    add(&mut hasher, value.as_bytes());
+                }
+                if let Some(script) = script(flag) {
+                    hash_file(&mut hasher, script, &wild_plan.search_paths, 0).ok()?;
+                }
+            }
+            LinkArg::Input(input) => {
+                let state = input.state;
+                add(
+                    &mut hasher,
+                    &[
+                        state.as_needed.into(),
+                        state.whole_archive.into(),
+                        state.link_static.into(),
+                    ],
+                );
+                add(
+                    &mut hasher,
+                    &input.group.unwrap_or(usize::MAX).to_le_bytes(),
+                );
+                // Inputs are keyed by their contents, so temporary objects of the compiler can hit.
+                let path = match &input.kind {
+                    LinkInputKind::File(path) => path.clone(),
+                    LinkInputKind::Library(name) => {
+                        add(&mut hasher, name.as_bytes());
+                        find_library(name, state.link_static, &wild_plan.search_paths)?
+                    }
+                };
+                hash_file(&mut hasher, &path, &wild_plan.search_paths, 0).ok()?;
+            }
+        }
+    }
+    Some(hasher.finalize().to_hex().to_string())
+}
+
+/// The script a flag like `-T` or `--version-script` reads, if it's one of those.
+fn script(flag: &[OsString]) -> Option<&Path> {
+    match flag {
+        [option, value] if SCRIPT_OPTIONS.iter().any(|script| option == *script) => {
+            Some(Path::new(value))
+        }
+        [option] => SCRIPT_OPTIONS.iter().find_map(|script| {
+            option
+                .as_bytes()
+                .strip_prefix(script.as_bytes())
+                .and_then(|rest| rest.strip_prefix(b"="))
+                .map(|path| Path::new(OsStr::from_bytes(path)))
+        }),
+        _ => None,
+    }
+}
+
+fn hash_file(
+    hasher: &mut blake3::Hasher,
+    path: &Path,
+    search_paths: &[PathBuf],
+    depth: usize,
+) -> Result<()> {
+    let contents = std::fs::read(path)?;
+    add(hasher, &contents);
+    let is_script = !contents.starts_with(b"\x7fELF")
+        && !contents.starts_with(b"!<arch>\n")
+        && !contents.starts_with(b"!<thin>\n")
+        && !contents.contains(&0);
+    if !is_script {
+        return Ok(());
+    }
+    if depth == MAX_SCRIPT_DEPTH {
+        bail!("Linker scripts nested too deeply at {}", path.display());
+    }
+    // Files named by `GROUP`, `INPUT` and friends.
+    let script = String::from_utf8_lossy(&contents);
+    for token in script.split(|c: char| c.is_whitespace() || c == '(' || c == ')' || c == ',') {
+        let referenced = match token.strip_prefix("-l") {
+            Some(name) if !name.is_empty() => find_library(OsStr::new(name), false, search_paths),
+            _ => {
+                let token = Path::new(token);
+                if token.is_file() {
+                    Some(token.to_owned())
+                } else if token.is_relative() && !token.as_os_str().is_empty() {
+                    search_paths
+                        .iter()
+                        .map(|dir| dir.join(token))
+                        .find(|path| path.is_file())
+                } else {
+                    None
+                }
+            }
+        };
+        if let Some(referenced) = referenced {
+            hash_file(hasher, &referenced, search_paths, depth + 1)?;
+        }
+    }
+    Ok(())
+}
+
+/// Finds `-l<name>` like the linker does.
+pub(crate) fn find_library(
+    name: &OsStr,
+    link_static: bool,
+    search_paths: &[PathBuf],
+) -> Option<PathBuf> {
+    let file_names = match name.as_bytes().strip_prefix(b":") {
+        Some(file_name) => vec![OsStr::from_bytes(file_name).to_owned()],
+        None => {
+            let library = |extension: &str| {
+                let mut file_name = OsString::from("lib");
+                file_name.push(name);
+                file_name.push(extension);
+                file_name
+            };
+            if link_static {
+                vec![library(".a")]
+            } else {
+                vec![library(".so"), library(".a")]
+            }
+        }
+    };
+    search_paths.iter().find_map(|dir| {
+        file_names
+            .iter()
+            .map(|file_name| dir.join(file_name))
+            .find(|path| path.is_file())
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn plan(line: &str) -> LinkPlan {
+        LinkPlan::parse(&crate::shell::split(line.as_bytes()).unwrap()).unwrap()
+    }
+
+    fn key(line: &str) -> Option<String> {
+        let plan = plan(line);
+        super::key(&plan, &plan.to_args())
+    }
+
+    #[test]
+    fn keys_follow_inputs() {
+        let dir =
+            std::env::temp_dir().join(format!("wild-proxy-link-cache-{}", std::process::id()));
+        let lib = dir.join("lib");
+        std::fs::create_dir_all(&lib).unwrap();
+        std::fs::write(dir.join("main.o"), "main").unwrap();
+        std::fs::write(lib.join("libfoo.so.1"), "\x7fELF foo 1").unwrap();
+        std::fs::write(lib.join("libfoo.so"), "GROUP ( libfoo.so.1 )").unwrap();
+        std::fs::write(lib.join("libfoo.a"), "!<arch>\nstatic foo").unwrap();
+        let line = format!(
+            "ld -o {out} {main} -L{lib} -lfoo",
+            out = dir.join("out").display(),
+            main = dir.join("main.o").display(),
+            lib = lib.display()
+        );
+
+        let first = key(&line).unwrap();
+        assert_eq!(Some(&first), key(&line).as_ref());
+        // Only the contents of inputs count, not their names or where the output goes.
+        std::fs::write(dir.join("cc1234.o"), "main").unwrap();
+        let renamed = line.replace("main.o", "cc1234.o").replace("out ", "other ");
+        assert_eq!(Some(&first), key(&renamed).as_ref());
+        // A library referenced from a linker script changed.
+        std::fs::write(lib.join("libfoo.so.1"), "\x7fELF foo 2").unwrap();
+        let second = key(&line).unwrap();
+        assert_ne!(first, second);
+        // Statically linked, the shared library doesn't matter.
+        let static_line = line.replace("-lfoo", "-Bstatic -lfoo");
+        let static_key = key(&static_line).unwrap();
+        std::fs::write(lib.join("libfoo.so.1"), "\x7fELF foo 3").unwrap();
+        assert_eq!(Some(static_key), key(&static_line));
+        // Input changed.
+        std::fs::write(dir.join("main.o"), "main 2").unwrap();
+        assert_ne!(Some(second), key(&line));
+
+        // Option values are taken as they are, even when they name a file.
+        std::fs::write(dir.join("ld.so"), "\x7fELF ld 1").unwrap();
+        let interp = format!(
+            "{line} -soname ld.so -dynamic-linker {}",
+            dir.join("ld.so").display()
+        );
+        let interp_key = key(&interp).unwrap();
+        std::fs::write(dir.join("ld.so"), "\x7fELF ld 2").unwrap();
+        assert_eq!(Some(&interp_key), key(&interp).as_ref());
+        assert_ne!(
+            Some(interp_key),
+            key(&interp.replace("-soname ld.so", "-soname ld2.so"))
+        );
+        // GCC's resolution file is named after a new temporary file.
+        assert_eq!(
+            key(&format!("{line} -plugin-opt=-fresolution=/tmp/cc1.res")),
+            key(&format!("{line} -plugin-opt=-fresolution=/tmp/cc2.res"))
+        );
+        // Scripts are keyed by their contents.
+        std::fs::write(dir.join("v.map"), "{ global: *; };").unwrap();
+        let script = format!("{line} --version-script={}", dir.join("v.map").display());
+        let script_key = key(&script).unwrap();
+        std::fs::write(dir.join("v.map"), "{ local: *; };").unwrap();
+        assert_ne!(Some(script_key), key(&script));
+
+        assert_eq!(None, key(&line.replace("-lfoo", "-lmissing")));
+        assert_eq!(None, key(&format!("{line} -Map out.map")));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,629 @@
+//! The link command from the compiler dump, split into the parts that matter for linking. Inputs and
+//! the other flags stay in command line order, since flags like `-b` apply to the inputs after them.
+//! Flags that change how the following inputs are linked, like `--as-needed`, `-Bstatic` or
+//! `--push-state`, are recorded on each input instead, and [`LinkPlan::to_args`] spells them out
+//! again where they change.
+
+use anyhow::{Context, Result};
+use std::{
+    ffi::{OsStr, OsString},
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+/// Options kept as flags that take their value as the next argument. Unknown options are passed
+/// through as they are, a separate value then stays right behind them as an input.
+const OPTIONS_WITH_VALUE: &[&str] = &[
+    "-a",
+    "-A",
+    "--architecture",
+    "-b",
+    "--format",
+    "-c",
+    "--mri-script",
+    "-e",
+    "--entry",
+    "-f",
+    "--auxiliary",
+    "-F",
+    "--filter",
+    "-G",
+    "-h",
+    "-soname",
+    "--soname",
+    "-R",
+    "--just-symbols",
+    "-T",
+    "--script",
+    "-u",
+    "--undefined",
+    "-y",
+    "--trace-symbol",
+    "-Y",
+    "-z",
+    "-Map",
+    "--defsym",
+    "--wrap",
+    "-rpath",
+    "--rpath",
+    "-rpath-link",
+    "--rpath-link",
+    "--require-defined",
+    "--export-dynamic-symbol",
+    "--export-dynamic-symbol-list",
+    "--Map",
+    "--trace-symbol",
+    "-mllvm",
+    "--symbol-ordering-file",
+    "--call-graph-ordering-file",
+    "--section-ordering-file",
+    "--thread-count",
+    "--error-limit",
+    "-plugin-opt",
+    "--version-script",
+    "--dynamic-list",
+    "--sysroot",
+    "--exclude-libs",
+    "-init",
+    "-fini",
+    "--hash-style",
+    "--section-start",
+    "-Ttext",
+    "-Tdata",
+    "-Tbss",
+    "--image-base",
+    "--dependency-file",
+    "--audit",
+    "-P",
+    "--depaudit",
+    "--oformat",
+    "-oformat",
+    "--retain-symbols-file",
+    "--sort-section",
+    "--spare-dynamic-tags",
+];
+
+/// Emulations recognised in the joined form `-m<emulation>`, other options starting with `-m` are
+/// kept as flags.
+const EMULATIONS: &[&str] = &[
+    "elf_x86_64",
+    "elf_x86_64_sol2",
+    "elf32_x86_64",
+    "elf_i386",
+    "elf_iamcu",
+    "aarch64elf",
+    "aarch64elfb",
+    "aarch64linux",
+    "aarch64linuxb",
+    "armelf",
+    "armelf_linux_eabi",
+    "armelfb_linux_eabi",
+    "elf64lriscv",
+    "elf32lriscv",
+    "elf64ppc",
+    "elf64lppc",
+    "elf32ppclinux",
+    "elf64_s390",
+    "elf_s390",
+    "elf64loongarch",
+];
+
+/// A link split into its parts. Parsed from the link command of the compiler dump and turned back
+/// into linker arguments with [`LinkPlan::to_args`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LinkPlan {
+    /// The program the compiler would run, usually `collect2` or `ld`.
+    pub linker: PathBuf,
+    /// `-m <emulation>`
+    pub emulation: Option<OsString>,
+    /// `-o <output>`
+    pub output: Option<PathBuf>,
+    /// `-dynamic-linker <path>`
+    pub dynamic_linker: Option<PathBuf>,
+    /// `-L` directories, in order.
+    pub search_paths: Vec<PathBuf>,
+    /// `-plugin <path>`
+    pub plugin: Option<PathBuf>,
+    /// Values of `-plugin-opt=`, in order.
+    pub plugin_options: Vec<OsString>,
+    /// Inputs and everything else, in command line order.
+    pub args: Vec<LinkArg>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkArg {
+    /// An object, archive, script or library.
+    Input(LinkInput),
+    /// Any other option, with its value if it takes one, passed through as is.
+    Flag(Vec<OsString>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkInput {
+    pub kind: LinkInputKind,
+    pub state: InputState,
+    /// Inputs between the same `--start-group` and `--end-group` share the number.
+    pub group: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkInputKind {
+    /// An object, archive, shared library or linker script given by path.
+    File(PathBuf),
+    /// `-l<name>`, `name` starts with `:` for `-l:libfoo.a`.
+    Library(OsString),
+}
+
+/// Flags in effect when an input comes up on the command line.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InputState {
+    /// `--as-needed`
+    pub as_needed: bool,
+    /// `--whole-archive`
+    pub whole_archive: bool,
+    /// `-Bstatic`, also set by `-static`.
+    pub link_static: bool,
+}
+
+impl LinkPlan {
+    /// Parses a link command, starting with the linker program.
+    pub fn parse(command: &[OsString]) -> Result<Self> {
+        let (linker, args) = command.split_first().context("Empty link command")?;
+        let mut plan = LinkPlan {
+            linker: PathBuf::from(linker),
+            ..Default::default()
+        };
+        let mut state = InputState::default();
+        let mut saved_states = Vec::new();
+        let mut group = None;
+        let mut groups = 0;
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            let mut value = || {
+                iter.next()
+                    .cloned()
+                    .with_context(|| format!("Missing value for `{}`", arg.to_string_lossy()))
+            };
+            let input = |kind| LinkArg::Input(LinkInput { kind, state, group });
+            match arg.to_str().unwrap_or_default() {
+                "-m" => plan.emulation = Some(value()?),
+                "-o" | "--output" => plan.output = Some(value()?.into()),
+                "-dynamic-linker" | "--dynamic-linker" | "-I" => {
+                    plan.dynamic_linker = Some(value()?.into());
+                }
+                "-L" | "--library-path" => plan.search_paths.push(value()?.into()),
+                "-l" | "--library" => {
+                    let library = value()?;
+                    plan.args.push(input(LinkInputKind::Library(library)));
+                }
+                "-plugin" | "--plugin" => plan.plugin = Some(value()?.into()),
+                "-plugin-opt" | "--plugin-opt" => plan.plugin_options.push(value()?),
+                "--as-needed" => state.as_needed = true,
+                "--no-as-needed" => state.as_needed = false,
+                "--whole-archive" => state.whole_archive = true,
+                "--no-whole-archive" => state.whole_archive = false,
+                "-Bstatic" | "-dn" | "-non_shared" => state.link_static = true,
+                "-Bdynamic" | "-dy" | "-call_shared" => state.link_static = false,
+                "-static" => {
+                    // Also a global flag, e.g. for `--no-dynamic-linker` behaviour.
+                    state.link_static = true;
+                    plan.args.push(LinkArg::Flag(vec![arg.clone()]));
+                }
+                "--push-state" => saved_states.push(state),
+                "--pop-state" => state = saved_states.pop().unwrap_or_default(),
+                "--start-group" | "-(" => {
+                    group = Some(groups);
+                    groups += 1;
+                }
+                "--end-group" | "-)" => group = None,
+                option if OPTIONS_WITH_VALUE.contains(&option) => {
+                    let value = value()?;
+                    plan.args.push(LinkArg::Flag(vec![arg.clone(), value]));
+                }
+                _ => {
+                    if let Some(emulation) = strip_prefix(arg, "-m")
+                        .filter(|emulation| EMULATIONS.iter().any(|known| *emulation == *known))
+                    {
+                        plan.emulation = Some(emulation.to_owned());
+                    } else if let Some(output) = strip_prefix(arg, "--output=").or_else(|| {
+                        strip_prefix(arg, "-o").filter(|_| !arg.as_bytes().starts_with(b"-oformat"))
+                    }) {
+                        plan.output = Some(output.into());
+                    } else if let Some(dynamic_linker) = strip_prefix(arg, "--dynamic-linker=")
+                        .or_else(|| strip_prefix(arg, "-dynamic-linker="))
+                    {
+                        plan.dynamic_linker = Some(dynamic_linker.into());
+                    } else if let Some(dir) =
+                        strip_prefix(arg, "--library-path=").or_else(|| strip_prefix(arg, "-L"))
+                    {
+                        plan.search_paths.push(dir.into());
+                    } else if let Some(library) =
+                        strip_prefix(arg, "--library=").or_else(|| strip_prefix(arg, "-l"))
+                    {
+                        plan.args
+                            .push(input(LinkInputKind::Library(library.to_owned())));
+                    } else if let Some(option) = strip_prefix(arg, "-plugin-opt=")
+                        .or_else(|| strip_prefix(arg, "--plugin-opt="))
+                    {
+                        plan.plugin_options.push(option.to_owned());
+                    } else if let Some(plugin) = strip_prefix(arg, "--plugin=") {
+                        plan.plugin = Some(plugin.into());
+                    } else if arg.as_bytes().starts_with(b"-") && arg != "-" {
+                        plan.args.push(LinkArg::Flag(vec![arg.clone()]));
+                    } else {
+                        plan.args.push(input(LinkInputKind::File(arg.into())));
+                    }
+                }
+            }
+        }
+
+        Ok(plan)
+    }
+
+    /// Objects, archives, scripts and libraries in command line order, including the start and end
+    /// files.
+    pub fn inputs(&self) -> impl Iterator<Item = &LinkInput> {
+        self.args.iter().filter_map(|arg| match arg {
+            LinkArg::Input(input) => Some(input),
+            LinkArg::Flag(_) => None,
+        })
+    }
+
+    /// The flags with their values, in command line order.
+    pub fn flags(&self) -> impl Iterator<Item = &OsString> {
+        self.args.iter().flat_map(|arg| match arg {
+            LinkArg::Input(_) => &[][..],
+            LinkArg::Flag(flag) => flag.as_slice(),
+        })
+    }
+
+    /// Start files like `Scrt1.o`, `crti.o` and `crtbeginS.o` before the other inputs.
+    pub fn crt_start(&self) -> Vec<&Path> {
+        self.inputs()
+            .map_while(|input| input.file_named(is_start_file))
+            .collect()
+    }
+
+    /// End files like `crtendS.o` and `crtn.o` after the other inputs.
+    pub fn crt_end(&self) -> Vec<&Path> {
+        let inputs = self.inputs().collect::<Vec<_>>();
+        let mut end = inputs
+            .iter()
+            .rev()
+            .map_while(|input| input.file_named(is_end_file))
+            .collect::<Vec<_>>();
+        end.reverse();
+        end
+    }
+
+    /// Returns the arguments for the linker, without the linker itself.
+    pub fn to_args(&self) -> Vec<OsString> {
+        let mut args = Vec::new();
+        if let Some(emulation) = &self.emulation {
+            args.extend(["-m".into(), emulation.clone()]);
+        }
+        if let Some(output) = &self.output {
+            args.extend(["-o".into(), output.into()]);
+        }
+        if let Some(dynamic_linker) = &self.dynamic_linker {
+            args.extend(["-dynamic-linker".into(), dynamic_linker.into()]);
+        }
+        if let Some(plugin) = &self.plugin {
+            args.extend(["-plugin".into(), plugin.into()]);
+        }
+        args.extend(
+            self.plugin_options
+                .iter()
+                .map(|option| joined("-plugin-opt=", option)),
+        );
+        args.extend(
+            self.search_paths
+                .iter()
+                .map(|dir| joined("-L", dir.as_os_str())),
+        );
+
+        let mut state = InputState::default();
+        let mut group = None;
+        for arg in &self.args {
+            let input = match arg {
+                LinkArg::Flag(flag) => {
+                    if flag[0] == "-static" {
+                        state.link_static = true;
+                    }
+                    args.extend(flag.iter().cloned());
+                    continue;
+                }
+                LinkArg::Input(input) => input,
+            };
+            if input.group != group {
+                if group.is_some() {
+                    args.push("--end-group".into());
+                }
+                if input.group.is_some() {
+                    args.push("--start-group".into());
+                }
+                group = input.group;
+            }
+            for (was, is, on, off) in [
+                (
+                    state.as_needed,
+                    input.state.as_needed,
+                    "--as-needed",
+                    "--no-as-needed",
+                ),
+                (
+                    state.whole_archive,
+                    input.state.whole_archive,
+                    "--whole-archive",
+                    "--no-whole-archive",
+                ),
+                (
+                    state.link_static,
+                    input.state.link_static,
+                    "-Bstatic",
+                    "-Bdynamic",
+                ),
+            ] {
+                if was != is {
+                    args.push(if is { on } else { off }.into());
+                }
+            }
+            state = input.state;
+            args.push(match &input.kind {
+                LinkInputKind::File(path) => path.into(),
+                LinkInputKind::Library(name) => joined("-l", name),
+            });
+        }
+        if group.is_some() {
+            args.push("--end-group".into());
+        }
+        if state.whole_archive {
+            // Would otherwise apply to archives the compiler runtime adds behind our back.
+            args.push("--no-whole-archive".into());
+        }
+        args
+    }
+}
+
+impl LinkInput {
+    /// The path of the input if it's a file whose name matches `predicate`.
+    fn file_named(&self, predicate: impl Fn(&str) -> bool) -> Option<&Path> {
+        match &self.kind {
+            LinkInputKind::File(path) => path
+                .file_name()
+                .and_then(OsStr::to_str)
+                .is_some_and(predicate)
+                .then_some(path.as_path()),
+            LinkInputKind::Library(_) => None,
+        }
+    }
+}
+
+fn is_start_file(name: &str) -> bool {
+    matches!(
+        name,
+        "crt0.o" | "crt1.o" | "Scrt1.o" | "gcrt1.o" | "rcrt1.o" | "grcrt1.o" | "Mcrt1.o" | "crti.o"
+    ) || (name.starts_with("crtbegin") && name.ends_with(".o"))
+}
+
+fn is_end_file(name: &str) -> bool {
+    name == "crtn.o" || (name.starts_with("crtend") && name.ends_with(".o"))
+}
+
+fn strip_prefix<'a>(arg: &'a OsStr, prefix: &str) -> Option<&'a OsStr> {
+    arg.as_bytes()
+        .strip_prefix(prefix.as_bytes())
+        .filter(|rest| !rest.is_empty())
+        .map(OsStr::from_bytes)
+}
+
+fn joined(option: &str, value: &OsStr) -> OsString {
+    let mut arg = OsString::from(option);
+    arg.push(value);
+    arg
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn args(line: &str) -> Vec<OsString> {
+        crate::shell::split(line.as_bytes()).unwrap()
+    }
+
+    fn library(name: &str, state: InputState, group: Option<usize>) -> LinkInput {
+        LinkInput {
+            kind: LinkInputKind::Library(name.into()),
+            state,
+            group,
+        }
+    }
+
+    fn flag(flag: &str) -> LinkArg {
+        LinkArg::Flag(vec![flag.into()])
+    }
+
+    fn file(path: &str, state: InputState) -> LinkArg {
+        LinkArg::Input(LinkInput {
+            kind: LinkInputKind::File(path.into()),
+            state,
+            group: None,
+        })
+    }
+
+    const GCC_LINK: &str = r#"/usr/lib/gcc/x86_64-linux-gnu/12/collect2 -plugin /usr/lib/gcc/x86_64-linux-gnu/12/liblto_plugin.so "-plugin-opt=/usr/lib/gcc/x86_64-linux-gnu/12/lto-wrapper" "-plugin-opt=-pass-through=-lc" --build-id --eh-frame-hdr -m elf_x86_64 "--hash-style=gnu" --as-needed -dynamic-linker /lib64/ld-linux-x86-64.so.2 -pie -o hello /usr/lib/gcc/x86_64-linux-gnu/12/../../../x86_64-linux-gnu/Scrt1.o /usr/lib/gcc/x86_64-linux-gnu/12/../../../x86_64-linux-gnu/crti.o /usr/lib/gcc/x86_64-linux-gnu/12/crtbeginS.o -L/usr/lib/gcc/x86_64-linux-gnu/12 -L/lib/x86_64-linux-gnu /tmp/ccHn0GQc.o -z now -lgcc --push-state --as-needed -lgcc_s --pop-state -lc /usr/lib/gcc/x86_64-linux-gnu/12/crtendS.o /usr/lib/gcc/x86_64-linux-gnu/12/../../../x86_64-linux-gnu/crtn.o"#;
+
+    #[test]
+    fn parse_gcc_link() {
+        let plan = LinkPlan::parse(&args(GCC_LINK)).unwrap();
+        let as_needed = InputState {
+            as_needed: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            LinkPlan {
+                linker: "/usr/lib/gcc/x86_64-linux-gnu/12/collect2".into(),
+                emulation: Some("elf_x86_64".into()),
+                output: Some("hello".into()),
+                dynamic_linker: Some("/lib64/ld-linux-x86-64.so.2".into()),
+                search_paths: vec![
+                    "/usr/lib/gcc/x86_64-linux-gnu/12".into(),
+                    "/lib/x86_64-linux-gnu".into(),
+                ],
+                args: vec![
+                    flag("--build-id"),
+                    flag("--eh-frame-hdr"),
+                    flag("--hash-style=gnu"),
+                    flag("-pie"),
+                    file(
+                        "/usr/lib/gcc/x86_64-linux-gnu/12/../../../x86_64-linux-gnu/Scrt1.o",
+                        as_needed,
+                    ),
+                    file(
+                        "/usr/lib/gcc/x86_64-linux-gnu/12/../../../x86_64-linux-gnu/crti.o",
+                        as_needed,
+                    ),
+                    file("/usr/lib/gcc/x86_64-linux-gnu/12/crtbeginS.o", as_needed),
+                    file("/tmp/ccHn0GQc.o", as_needed),
+                    LinkArg::Flag(args("-z now")),
+                    LinkArg::Input(library("gcc", as_needed, None)),
+                    LinkArg::Input(library("gcc_s", as_needed, None)),
+                    LinkArg::Input(library("c", as_needed, None)),
+                    file("/usr/lib/gcc/x86_64-linux-gnu/12/crtendS.o", as_needed),
+                    file(
+                        "/usr/lib/gcc/x86_64-linux-gnu/12/../../../x86_64-linux-gnu/crtn.o",
+                        as_needed,
+                    ),
+                ],
+                plugin: Some("/usr/lib/gcc/x86_64-linux-gnu/12/liblto_plugin.so".into()),
+                plugin_options: vec![
+                    "/usr/lib/gcc/x86_64-linux-gnu/12/lto-wrapper".into(),
+                    "-pass-through=-lc".into(),
+                ],
+            },
+            plan
+        );
+        assert_eq!(
+            vec![
+                Path::new("/usr/lib/gcc/x86_64-linux-gnu/12/../../../x86_64-linux-gnu/Scrt1.o"),
+                Path::new("/usr/lib/gcc/x86_64-linux-gnu/12/../../../x86_64-linux-gnu/crti.o"),
+                Path::new("/usr/lib/gcc/x86_64-linux-gnu/12/crtbeginS.o"),
+            ],
+            plan.crt_start()
+        );
+        assert_eq!(
+            vec![
+                Path::new("/usr/lib/gcc/x86_64-linux-gnu/12/crtendS.o"),
+                Path::new("/usr/lib/gcc/x86_64-linux-gnu/12/../../../x86_64-linux-gnu/crtn.o"),
+            ],
+            plan.crt_end()
+        );
+        assert_eq!(
+            args(
+                "-m elf_x86_64 -o hello -dynamic-linker /lib64/ld-linux-x86-64.so.2 \
+                 -plugin /usr/lib/gcc/x86_64-linux-gnu/12/liblto_plugin.so \
+                 -plugin-opt=/usr/lib/gcc/x86_64-linux-gnu/12/lto-wrapper \
+                 -plugin-opt=-pass-through=-lc \
+                 -L/usr/lib/gcc/x86_64-linux-gnu/12 -L/lib/x86_64-linux-gnu \
+                 --build-id --eh-frame-hdr --hash-style=gnu -pie --as-needed \
+                 /usr/lib/gcc/x86_64-linux-gnu/12/../../../x86_64-linux-gnu/Scrt1.o \
+                 /usr/lib/gcc/x86_64-linux-gnu/12/../../../x86_64-linux-gnu/crti.o \
+                 /usr/lib/gcc/x86_64-linux-gnu/12/crtbeginS.o \
+                 /tmp/ccHn0GQc.o -z now -lgcc -lgcc_s -lc \
+                 /usr/lib/gcc/x86_64-linux-gnu/12/crtendS.o \
+                 /usr/lib/gcc/x86_64-linux-gnu/12/../../../x86_64-linux-gnu/crtn.o"
+            ),
+            plan.to_args()
+        );
+    }
+
+    #[test]
+    fn input_state_and_groups() {
+        let plan = LinkPlan::parse(&args(
+            "ld -static -o out main.o --whole-archive libfoo.a --no-whole-archive \
+             --start-group -lgcc -lgcc_eh -lc --end-group -Bdynamic -l:libbar.so.1",
+        ))
+        .unwrap();
+        let link_static = InputState {
+            link_static: true,
+            ..Default::default()
+        };
+        let whole_archive = InputState {
+            whole_archive: true,
+            link_static: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            vec![
+                LinkInput {
+                    kind: LinkInputKind::File("main.o".into()),
+                    state: link_static,
+                    group: None,
+                },
+                LinkInput {
+                    kind: LinkInputKind::File("libfoo.a".into()),
+                    state: whole_archive,
+                    group: None,
+                },
+                library("gcc", link_static, Some(0)),
+                library("gcc_eh", link_static, Some(0)),
+                library("c", link_static, Some(0)),
+                library(":libbar.so.1", InputState::default(), None),
+            ],
+            plan.inputs().cloned().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            args(
+                "-o out -static main.o --whole-archive libfoo.a --start-group \
+                 --no-whole-archive -lgcc -lgcc_eh -lc --end-group -Bdynamic -l:libbar.so.1"
+            ),
+            plan.to_args()
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let plan = LinkPlan::parse(&args(GCC_LINK)).unwrap();
+        let mut command = vec![plan.linker.clone().into_os_string()];
+        command.extend(plan.to_args());
+        assert_eq!(plan, LinkPlan::parse(&command).unwrap());
+
+        assert!(LinkPlan::parse(&args("ld -o")).is_err());
+    }
+
+    #[test]
+    fn options_keep_their_place() {
+        let line = "-m elf_x86_64 -o out main.o --require-defined foo -lc --soname libx.so \
+                    --export-dynamic-symbol bar --defsym a=b -Map out.map --version-script v.map \
+                    -b binary data.bin -b elf64-x86-64 more.o -mllvm -foo --unknown";
+        let plan = LinkPlan::parse(&args(&format!("ld {line}"))).unwrap();
+        assert_eq!(Some(OsString::from("elf_x86_64")), plan.emulation);
+        assert_eq!(
+            vec![
+                LinkInputKind::File("main.o".into()),
+                LinkInputKind::Library("c".into()),
+                LinkInputKind::File("data.bin".into()),
+                LinkInputKind::File("more.o".into()),
+            ],
+            plan.inputs()
+                .map(|input| input.kind.clone())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(args(line), plan.to_args());
+
+        // Only known emulations are taken from the joined form.
+        let plan = LinkPlan::parse(&args("ld -melf_i386 -mllvm=-foo main.o")).unwrap();
+        assert_eq!(Some(OsString::from("elf_i386")), plan.emulation);
+        assert_eq!(args("-m elf_i386 -mllvm=-foo main.o"), plan.to_args());
+        // Unknown options with a separate value stay right in front of it.
+        let line = "-o out --made-up value main.o -oformat binary";
+        assert_eq!(
+            args(line),
+            LinkPlan::parse(&args(&format!("ld {line}")))
+                .unwrap()
+                .to_args()
+        );
+        assert!(LinkPlan::parse(&[]).is_err());
+    }
+}
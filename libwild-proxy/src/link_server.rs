@@ -0,0 +1,541 @@
+//! Optional prefetching link server, started with `wild-proxy serve`. It listens on a per-user Unix
+//! socket and links for proxies, keeping the system libraries it has linked mapped so they stay in
+//! the page cache. That is all it keeps: libwild sets up its global state once per process, so each
+//! link parses its arguments and runs in a fresh child forked from the server, just like an
+//! in-process link would.
+//!
+//! Proxies send Wild's arguments, i.e. their `LinkPlan` after dialect translation and rewrite
+//! rules, their working directory and the variables that change the link: `WILD_*` ones libwild
+//! reads and the jobserver. A jobserver given as file descriptors is sent as the path of the proxy's
+//! descriptor in `/proc`, which the server can open while the proxy waits for the result.
+
+use crate::{
+    link_cache::find_library,
+    link_plan::{LinkInputKind, LinkPlan},
+};
+use anyhow::{Context, Result, anyhow, bail};
+use std::{
+    collections::HashMap,
+    ffi::{OsStr, OsString},
+    fs::File,
+    io::{Read, Seek, Write},
+    os::{
+        fd::{AsRawFd, FromRawFd},
+        unix::{
+            ffi::{OsStrExt, OsStringExt},
+            fs::{DirBuilderExt, FileTypeExt, MetadataExt, PermissionsExt},
+            net::{UnixListener, UnixStream},
+        },
+    },
+    path::{Path, PathBuf},
+};
+
+const SOCKET_ENV: &str = "WILD_PROXY_SERVER_SOCKET";
+
+/// Sent first, servers only link for proxies of the same version.
+const VERSION: &str = concat!("wild-proxy ", env!("CARGO_PKG_VERSION"));
+
+/// Variables the jobserver client in libwild reads.
+const JOBSERVER_ENV_VARS: &[&str] = &["CARGO_MAKEFLAGS", "MAKEFLAGS", "MFLAGS"];
+
+/// Libraries under these directories are kept mapped.
+const SYSTEM_DIRS: &[&str] = &["/usr/lib", "/usr/lib64", "/lib", "/lib64", "/usr/local/lib"];
+
+/// How many system libraries are kept mapped at most.
+const MAX_MAPPINGS: usize = 512;
+
+const OK: &[u8] = b"ok";
+const ERROR: &[u8] = b"error";
+const UNAVAILABLE: &[u8] = b"unavailable";
+
+/// The socket of the current user's server, `WILD_PROXY_SERVER_SOCKET` if set.
+pub(crate) fn socket_path() -> PathBuf {
+    if let Some(path) = std::env::var_os(SOCKET_ENV).filter(|path| !path.is_empty()) {
+        return path.into();
+    }
+    match std::env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty()) {
+        Some(dir) => Path::new(&dir).join("wild-proxy.sock"),
+        None => {
+            // SAFETY: getuid can't fail.
+            let uid = unsafe { libc::getuid() };
+            std::env::temp_dir().join(format!("wild-proxy-{uid}/link.sock"))
+        }
+    }
+}
+
+/// Links `wild_args` on the server. Returns `None` if there's no server to do it.
+pub(crate) fn link(wild_args: &[OsString], verbose: bool) -> Option<Result<()>> {
+    let path = socket_path();
+    // A socket someone else made could write anything anywhere we can.
+    // SAFETY: getuid can't fail.
+    if path.metadata().ok()?.uid() != unsafe { libc::getuid() } {
+        tracing::warn!("Not using {}, it belongs to another user", path.display());
+        return None;
+    }
+    let mut stream = match UnixStream::connect(&path) {
+        Ok(stream) => stream,
+        Err(e) => {
+            tracing::debug!("No link server at {}: {e}", path.display());
+            return None;
+        }
+    };
+    let response = request(&mut stream, wild_args);
+    let [kind, stdout, stderr, message] = match response.as_deref() {
+        Ok([kind, stdout, stderr, message]) => [kind, stdout, stderr, message],
+        Ok(_) => {
+            tracing::warn!("Invalid response from the link server, linking in-process");
+            return None;
+        }
+        Err(e) => {
+            tracing::warn!("Link server failed ({e}), linking in-process");
+            return None;
+        }
+    };
+    let _ = std::io::stdout().write_all(stdout);
+    let _ = std::io::stderr().write_all(stderr);
+    if verbose && kind != UNAVAILABLE {
+        eprintln!(
+            "wild-proxy: linked by the link server at {}",
+            path.display()
+        );
+    }
+    match kind.as_slice() {
+        OK => Some(Ok(())),
+        ERROR => Some(Err(anyhow!("{}", String::from_utf8_lossy(message)))),
+        _ => {
+            tracing::debug!(
+                "Link server is {}, linking in-process",
+                String::from_utf8_lossy(message)
+            );
+            None
+        }
+    }
+}
+
+fn request(stream: &mut UnixStream, wild_args: &[OsString]) -> std::io::Result<Vec<Vec<u8>>> {
+    let cwd = std::env::current_dir()?;
+    write_frame(stream, VERSION.as_bytes())?;
+    write_frame(stream, cwd.as_os_str().as_bytes())?;
+    write_frame(stream, &forwarded_env(std::env::vars_os()))?;
+    for arg in wild_args {
+        write_frame(stream, arg.as_bytes())?;
+    }
+    stream.shutdown(std::net::Shutdown::Write)?;
+    read_frames(stream)
+}
+
+/// Whether `name` is a variable that changes the link, and is taken over by the server.
+fn is_forwarded(name: &[u8]) -> bool {
+    JOBSERVER_ENV_VARS.iter().any(|var| name == var.as_bytes())
+        || (name.starts_with(b"WILD_") && !name.starts_with(b"WILD_PROXY_"))
+}
+
+/// The variables of `vars` that change the link, as `NAME=value` separated by NUL bytes.
+fn forwarded_env(vars: impl Iterator<Item = (OsString, OsString)>) -> Vec<u8> {
+    let mut env = Vec::new();
+    for (name, value) in vars {
+        if !is_forwarded(name.as_bytes()) {
+            continue;
+        }
+        let value = if JOBSERVER_ENV_VARS.iter().any(|var| name == *var) {
+            jobserver_by_path(value.as_bytes())
+        } else {
+            value.into_vec()
+        };
+        env.extend_from_slice(name.as_bytes());
+        env.push(b'=');
+        env.extend_from_slice(&value);
+        env.push(0);
+    }
+    env
+}
+
+/// Replaces a jobserver given as file descriptors in `MAKEFLAGS` with a path to the read end, which
+/// works for other processes too. Descriptors that aren't a pipe are dropped, like the compiler
+/// driver drops them.
+fn jobserver_by_path(makeflags: &[u8]) -> Vec<u8> {
+    let words = makeflags.split(|&b| b == b' ').filter_map(|word| {
+        let Some(fds) = [b"--jobserver-auth=".as_slice(), b"--jobserver-fds="]
+            .iter()
+            .find_map(|option| word.strip_prefix(*option))
+        else {
+            return Some(word.to_vec());
+        };
+        let Some(read_fd) = std::str::from_utf8(fds)
+            .ok()
+            .and_then(|fds| fds.split_once(','))
+            .and_then(|(read, _)| read.parse::<u32>().ok())
+        else {
+            // A path already, e.g. `fifo:/tmp/GMfifo123`, or none at all, `-2,-2`.
+            return Some(word.to_vec());
+        };
+        let path = format!("/proc/{}/fd/{read_fd}", std::process::id());
+        Path::new(&path)
+            .metadata()
+            .is_ok_and(|metadata| metadata.file_type().is_fifo())
+            .then(|| format!("--jobserver-auth=fifo:{path}").into_bytes())
+    });
+    words.collect::<Vec<_>>().join(&b' ')
+}
+
+/// Runs the link server until it's killed.
+pub fn serve() -> Result<()> {
+    let path = socket_path();
+    let dir = path.parent().context("Invalid socket path")?;
+    std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+        .with_context(|| format!("Failed to create {}", dir.display()))?;
+    let metadata = dir.metadata()?;
+    // SAFETY: getuid can't fail.
+    if metadata.uid() != unsafe { libc::getuid() } || metadata.permissions().mode() & 0o077 != 0 {
+        bail!(
+            "{} must belong to the current user and be private to it",
+            dir.display()
+        );
+    }
+    if UnixStream::connect(&path).is_ok() {
+        bail!("A link server is already listening on {}", path.display());
+    }
+    // Left behind by a server that was killed.
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path)
+        .with_context(|| format!("Failed to listen on {}", path.display()))?;
+    eprintln!("wild-proxy: link server listening on {}", path.display());
+
+    // Children exit on their own, nobody waits for them.
+    // SAFETY: Only changes how SIGCHLD is handled.
+    unsafe { libc::signal(libc::SIGCHLD, libc::SIG_IGN) };
+    let mut server = Server::default();
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                if let Err(e) = server.handle(stream) {
+                    tracing::warn!("Failed to handle a link: {e:?}");
+                }
+            }
+            Err(e) => tracing::warn!("Failed to accept a link: {e}"),
+        }
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+struct Server {
+    mappings: HashMap<PathBuf, Mapping>,
+}
+
+impl Server {
+    /// Reads a link request and links it in a child process, which has libwild's global state to
+    /// itself and links while the server takes the next request.
+    fn handle(&mut self, mut stream: UnixStream) -> Result<()> {
+        let mut frames = read_frames(&mut stream)?.into_iter();
+        if frames.next().as_deref() != Some(VERSION.as_bytes()) {
+            return write_response(&mut stream, UNAVAILABLE, b"", b"", b"of another version");
+        }
+        let cwd = PathBuf::from(OsString::from_vec(
+            frames.next().context("Missing working directory")?,
+        ));
+        let env = frames.next().context("Missing environment")?;
+        let wild_args = frames.map(OsString::from_vec).collect::<Vec<_>>();
+
+        let _ = std::io::stdout().flush();
+        let _ = std::io::stderr().flush();
+        // SAFETY: The server doesn't start threads, the child only links and exits.
+        match unsafe { libc::fork() } {
+            0 => {
+                let status = match link_in_child(&mut stream, &cwd, &env, &wild_args) {
+                    Ok(()) => 0,
+                    Err(e) => {
+                        tracing::warn!("Failed to link: {e:?}");
+                        1
+                    }
+                };
+                // SAFETY: Exits the child without running anything of the server's.
+                unsafe { libc::_exit(status) }
+            }
+            -1 => {
+                let error = std::io::Error::last_os_error();
+                write_response(&mut stream, UNAVAILABLE, b"", b"", b"unable to fork")?;
+                Err(error).context("Failed to fork")
+            }
+            _ => {
+                drop(stream);
+                self.map_system_libraries(&wild_args);
+                Ok(())
+            }
+        }
+    }
+
+    /// Maps the system libraries of a link, dropping maps of libraries that changed.
+    fn map_system_libraries(&mut self, wild_args: &[OsString]) {
+        let Ok(plan) = LinkPlan::parse(&[&[OsString::from("wild")], wild_args].concat()) else {
+            return;
+        };
+        let libraries = plan.inputs().filter_map(|input| match &input.kind {
+            LinkInputKind::File(path) => Some(path.clone()),
+            LinkInputKind::Library(name) => {
+                find_library(name, input.state.link_static, &plan.search_paths)
+            }
+        });
+        for library in libraries {
+            let Ok(path) = library.canonicalize() else {
+                continue;
+            };
+            if !SYSTEM_DIRS.iter().any(|dir| path.starts_with(dir)) {
+                continue;
+            }
+            let Ok(metadata) = path.metadata() else {
+                continue;
+            };
+            if self.mappings.get(&path).is_some_and(|mapping| {
+                mapping.mtime == metadata.mtime() && mapping.len == metadata.len() as usize
+            }) {
+                continue;
+            }
+            if self.mappings.len() >= MAX_MAPPINGS && !self.mappings.contains_key(&path) {
+                return;
+            }
+            match Mapping::new(&path) {
+                Ok(mapping) => {
+                    tracing::debug!("Mapped {}", path.display());
+                    self.mappings.insert(path, mapping);
+                }
+                Err(e) => tracing::debug!("Failed to map {}: {e}", path.display()),
+            }
+        }
+    }
+}
+
+/// A file mapped into memory for as long as the server runs.
+struct Mapping {
+    address: *mut libc::c_void,
+    len: usize,
+    mtime: i64,
+}
+
+impl Mapping {
+    fn new(path: &Path) -> std::io::Result<Self> {
+        let file = File::open(path)?;
+        let metadata = file.metadata()?;
+        let len = metadata.len() as usize;
+        if len == 0 {
+            return Err(std::io::ErrorKind::InvalidInput.into());
+        }
+        // SAFETY: A read-only, private map of a file we opened, unmapped on drop.
+        let address = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if address == libc::MAP_FAILED {
+            return Err(std::io::Error::last_os_error());
+        }
+        // SAFETY: The range was just mapped, the advice is only a hint.
+        unsafe { libc::madvise(address, len, libc::MADV_WILLNEED) };
+        Ok(Self {
+            address,
+            len,
+            mtime: metadata.mtime(),
+        })
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: Mapped in `new` and not used after this.
+        unsafe { libc::munmap(self.address, self.len) };
+    }
+}
+
+/// Links in the child process, with the proxy's `env` and the output of the link going to the
+/// proxy.
+fn link_in_child(
+    stream: &mut UnixStream,
+    cwd: &Path,
+    env: &[u8],
+    wild_args: &[OsString],
+) -> Result<()> {
+    // SAFETY: The child has a single thread, nothing reads the environment at the same time.
+    unsafe {
+        for (name, _) in std::env::vars_os() {
+            if is_forwarded(name.as_bytes()) {
+                std::env::remove_var(name);
+            }
+        }
+        for var in env.split(|&b| b == 0) {
+            if let Some(equals) = var.iter().position(|&b| b == b'=') {
+                std::env::set_var(
+                    OsStr::from_bytes(&var[..equals]),
+                    OsStr::from_bytes(&var[equals + 1..]),
+                );
+            }
+        }
+    }
+    // Wild prints warnings to standard output, errors to standard error.
+    let mut stdout = redirect(libc::STDOUT_FILENO)?;
+    let mut stderr = redirect(libc::STDERR_FILENO)?;
+    let result = std::env::set_current_dir(cwd)
+        .with_context(|| format!("Failed to change directory to {}", cwd.display()))
+        .and_then(|()| crate::run_wild(wild_args));
+    std::io::stdout().flush()?;
+    std::io::stderr().flush()?;
+    let (mut stdout_bytes, mut stderr_bytes) = (Vec::new(), Vec::new());
+    stdout.rewind()?;
+    stdout.read_to_end(&mut stdout_bytes)?;
+    stderr.rewind()?;
+    stderr.read_to_end(&mut stderr_bytes)?;
+    match result {
+        Ok(()) => write_response(stream, OK, &stdout_bytes, &stderr_bytes, b""),
+        Err(error) => write_response(
+            stream,
+            ERROR,
+            &stdout_bytes,
+            &stderr_bytes,
+            format!("{error:?}").as_bytes(),
+        ),
+    }
+}
+
+/// Points `fd` at a new memory file and returns the file.
+fn redirect(fd: libc::c_int) -> Result<File> {
+    // SAFETY: The name is a valid C string, the new descriptor is owned by the returned file.
+    let file = unsafe {
+        let memfd = libc::memfd_create(c"wild-proxy-output".as_ptr(), libc::MFD_CLOEXEC);
+        if memfd < 0 {
+            return Err(std::io::Error::last_os_error()).context("Failed to create a memory file");
+        }
+        File::from_raw_fd(memfd)
+    };
+    // SAFETY: Both descriptors are open.
+    if unsafe { libc::dup2(file.as_raw_fd(), fd) } < 0 {
+        return Err(std::io::Error::last_os_error()).context("Failed to redirect output");
+    }
+    Ok(file)
+}
+
+fn write_response(
+    stream: &mut UnixStream,
+    kind: &[u8],
+    stdout: &[u8],
+    stderr: &[u8],
+    message: &[u8],
+) -> Result<()> {
+    write_frame(stream, kind)?;
+    write_frame(stream, stdout)?;
+    write_frame(stream, stderr)?;
+    write_frame(stream, message)?;
+    Ok(())
+}
+
+/// Frames are a little-endian `u32` length and the bytes.
+fn write_frame(writer: &mut impl Write, bytes: &[u8]) -> std::io::Result<()> {
+    let len = u32::try_from(bytes.len()).map_err(|_| std::io::ErrorKind::InvalidInput)?;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(bytes)
+}
+
+/// Reads frames until the other end stops writing.
+fn read_frames(reader: &mut impl Read) -> std::io::Result<Vec<Vec<u8>>> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let mut frames = Vec::new();
+    let mut rest = bytes.as_slice();
+    while let Some((len, tail)) = rest.split_first_chunk::<4>() {
+        let len = u32::from_le_bytes(*len) as usize;
+        if tail.len() < len {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        frames.push(tail[..len].to_vec());
+        rest = &tail[len..];
+    }
+    if !rest.is_empty() {
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn frames() {
+        let mut bytes = Vec::new();
+        for frame in [&b"ok"[..], b"", b"warning\n"] {
+            write_frame(&mut bytes, frame).unwrap();
+        }
+        assert_eq!(
+            vec![b"ok".to_vec(), vec![], b"warning\n".to_vec()],
+            read_frames(&mut bytes.as_slice()).unwrap()
+        );
+        assert!(read_frames(&mut &bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn forwarded_variables() {
+        let vars = [
+            ("WILD_FILES_PER_GROUP", "4"),
+            ("WILD_PROXY_SHADOW", "system"),
+            ("PATH", "/usr/bin"),
+            ("MAKEFLAGS", "-j4 --jobserver-auth=fifo:/tmp/GMfifo1"),
+        ]
+        .map(|(name, value)| (OsString::from(name), OsString::from(value)));
+        assert_eq!(
+            b"WILD_FILES_PER_GROUP=4\0MAKEFLAGS=-j4 --jobserver-auth=fifo:/tmp/GMfifo1\0"
+                .as_slice(),
+            forwarded_env(vars.into_iter())
+        );
+    }
+
+    #[test]
+    fn jobserver_descriptors() {
+        let mut fds = [0; 2];
+        // SAFETY: `fds` has room for both ends.
+        assert_eq!(0, unsafe { libc::pipe(fds.as_mut_ptr()) });
+        let [read, write] = fds.map(|fd| {
+            // SAFETY: Just created and owned by nobody else.
+            unsafe { File::from_raw_fd(fd) }
+        });
+        let makeflags = format!(
+            " -j4 --jobserver-auth={},{}",
+            read.as_raw_fd(),
+            write.as_raw_fd()
+        );
+        assert_eq!(
+            format!(
+                " -j4 --jobserver-auth=fifo:/proc/{}/fd/{}",
+                std::process::id(),
+                read.as_raw_fd()
+            ),
+            String::from_utf8(jobserver_by_path(makeflags.as_bytes())).unwrap()
+        );
+
+        // Descriptors that aren't a jobserver are dropped.
+        let not_a_pipe = File::open("/dev/null").unwrap();
+        let makeflags = format!("-j4 --jobserver-fds={0},{0}", not_a_pipe.as_raw_fd());
+        assert_eq!(b"-j4".as_slice(), jobserver_by_path(makeflags.as_bytes()));
+        assert_eq!(
+            b"-j4 --jobserver-auth=-2,-2".as_slice(),
+            jobserver_by_path(b"-j4 --jobserver-auth=-2,-2")
+        );
+    }
+
+    #[test]
+    fn other_versions() {
+        let (mut client, server) = UnixStream::pair().unwrap();
+        write_frame(&mut client, b"wild-proxy 0.0.0").unwrap();
+        client.shutdown(std::net::Shutdown::Write).unwrap();
+        Server::default().handle(server).unwrap();
+        let response = read_frames(&mut client).unwrap();
+        assert_eq!(UNAVAILABLE, response[0]);
+        assert_eq!(b"of another version", response[3].as_slice());
+    }
+}
@@ -0,0 +1,112 @@
+//! Answers to build systems asking which linker the compiler uses. Meson runs `cc -Wl,--version`,
+//! CMake looks at `-Wl,-v` and libtool runs `cc -print-prog-name=ld` followed by `ld -v`. They
+//! should all see Wild, not the system linker, and without a full probe and link.
+
+use crate::compile_db::OPTIONS_WITH_VALUE;
+use anyhow::Result;
+use std::{
+    ffi::{OsStr, OsString},
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum LinkerProbe {
+    /// The linker is asked for its version, e.g. `-Wl,--version`.
+    Version,
+    /// `-print-prog-name=ld`
+    LinkerPath,
+}
+
+pub(crate) fn detect(args: &[OsString]) -> Option<LinkerProbe> {
+    if args.iter().any(|arg| {
+        matches!(
+            arg.to_str(),
+            Some("-print-prog-name=ld" | "--print-prog-name=ld")
+        )
+    }) {
+        return Some(LinkerProbe::LinkerPath);
+    }
+
+    let mut linker_flags = Vec::new();
+    let mut has_inputs = false;
+    // Arguments that aren't UTF-8 can only be inputs, or values of options we don't care about.
+    let mut iter = args.iter().map(|arg| arg.to_str().unwrap_or("input"));
+    while let Some(arg) = iter.next() {
+        if let Some(flags) = arg.strip_prefix("-Wl,") {
+            linker_flags.extend(flags.split(','));
+        } else if OPTIONS_WITH_VALUE.contains(&arg) {
+            if let Some(value) = iter.next()
+                && arg == "-Xlinker"
+            {
+                linker_flags.push(value);
+            }
+        } else if !arg.starts_with('-') || arg == "-" {
+            has_inputs = true;
+        }
+    }
+    // Like `ld`, `--version` exits right away while `-v` only prints the version before linking.
+    if linker_flags.contains(&"--version")
+        || (!has_inputs && linker_flags.iter().any(|flag| matches!(*flag, "-v" | "-V")))
+    {
+        return Some(LinkerProbe::Version);
+    }
+    None
+}
+
+/// Prints the same identity as `wild --version`.
+pub(crate) fn print_identity() -> Result<()> {
+    crate::run_wild(&["--version".into()])
+}
+
+/// Returns the `ld` impostor in the same directory as the impostor we were invoked as, if there is
+/// one.
+pub(crate) fn ld_impostor(zero_position_arg: &OsStr) -> Option<PathBuf> {
+    let invoked = Path::new(zero_position_arg);
+    let dir = if crate::has_dir(zero_position_arg) {
+        invoked.parent()?.to_owned()
+    } else {
+        let paths = std::env::var_os("PATH")?;
+        std::env::split_paths(&paths).find(|dir| dir.join(invoked).is_file())?
+    };
+    let own_id = crate::recursion::FileId::of(&std::env::current_exe().ok()?)?;
+    let ld = std::path::absolute(dir.join("ld")).ok()?;
+    (crate::recursion::FileId::of(&ld) == Some(own_id)).then_some(ld)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn detect_str(args: &[&str]) -> Option<LinkerProbe> {
+        detect(&args.iter().map(OsString::from).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn probes() {
+        assert_eq!(Some(LinkerProbe::Version), detect_str(&["-Wl,--version"]));
+        assert_eq!(
+            Some(LinkerProbe::Version),
+            detect_str(&["-O2", "-Wl,--as-needed,--version", "foo.c"])
+        );
+        assert_eq!(
+            Some(LinkerProbe::Version),
+            detect_str(&["-Xlinker", "--version"])
+        );
+        assert_eq!(Some(LinkerProbe::Version), detect_str(&["-Wl,-v"]));
+        assert_eq!(
+            Some(LinkerProbe::Version),
+            detect_str(&["-Wl,-V", "-o", "out"])
+        );
+        assert_eq!(
+            Some(LinkerProbe::LinkerPath),
+            detect_str(&["-print-prog-name=ld"])
+        );
+
+        assert_eq!(None, detect_str(&["-Wl,-v", "foo.c"]));
+        assert_eq!(None, detect_str(&["-v"]));
+        assert_eq!(None, detect_str(&["-Wl,--version-script,foo.map", "foo.o"]));
+        assert_eq!(None, detect_str(&["-print-prog-name=as"]));
+        assert_eq!(None, detect_str(&["foo.c", "-o", "foo"]));
+    }
+}
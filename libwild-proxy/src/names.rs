@@ -0,0 +1,504 @@
+//! Mapping from the name the impostor was invoked as to the real compiler driver it stands for.
+//!
+//! Configurable prefixes (`wild-` by default) are stripped first. `WILD_PROXY_CC` and
+//! `WILD_PROXY_CXX` override the driver for C and C++ names. Otherwise the exact name is
+//! preferred. Versioned names fall back to the unversioned driver if it reports the same version.
+//! Host triplet names fall back to the plain driver. Clang stands in for foreign triplets via
+//! `--target`.
+
+use std::{ffi::OsString, os::unix::fs::PermissionsExt};
+
+const DRIVERS: &[&str] = &[
+    "cc", "c++", "c89", "c99", "cpp", "gcc", "g++", "clang", "clang++", "gfortran",
+];
+
+const C_DRIVERS: &[&str] = &["cc", "c89", "c99", "gcc", "clang"];
+
+const CXX_DRIVERS: &[&str] = &["c++", "g++", "clang++"];
+
+/// Compiler wrappers that take the compiler as their first argument or masquerade as it.
+const COMPILER_WRAPPERS: &[&str] = &["ccache", "sccache", "distcc", "icecc"];
+
+/// Linkers the `ld` impostor can stand in for, optionally prefixed with a triplet.
+const LINKERS: &[&str] = &["ld", "ld.bfd", "ld.wild"];
+
+const DEFAULT_STRIP_PREFIXES: &str = "wild-";
+
+/// A compiler driver name split into its parts, e.g. `aarch64-linux-gnu-gcc-14`.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct DriverName<'a> {
+    pub(crate) triplet: Option<&'a str>,
+    pub(crate) driver: &'a str,
+    pub(crate) version: Option<&'a str>,
+}
+
+impl<'a> DriverName<'a> {
+    /// Returns `None` for anything that isn't a compiler driver we want an impostor for.
+    pub(crate) fn parse(name: &'a str) -> Option<Self> {
+        let (unversioned, version) = split_version(name);
+        if DRIVERS.contains(&unversioned) {
+            return Some(Self {
+                triplet: None,
+                driver: unversioned,
+                version,
+            });
+        }
+        DRIVERS.iter().find_map(|driver| {
+            let triplet = unversioned
+                .strip_suffix(driver)?
+                .strip_suffix('-')
+                .filter(|triplet| is_linux_triplet(triplet))?;
+            Some(Self {
+                triplet: Some(triplet),
+                driver,
+                version,
+            })
+        })
+    }
+}
+
+/// A program to look for in `PATH`, or a path if it contains a slash.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Candidate {
+    pub(crate) name: OsString,
+    /// Arguments to put before the user's, like `--target=` when Clang stands in for a cross
+    /// compiler.
+    pub(crate) prefix_args: Vec<String>,
+    /// The candidate is only acceptable if it reports this version.
+    pub(crate) version: Option<String>,
+}
+
+impl Candidate {
+    pub(crate) fn new(name: impl Into<OsString>) -> Self {
+        Self {
+            name: name.into(),
+            prefix_args: Vec::new(),
+            version: None,
+        }
+    }
+}
+
+/// Returns the real compilers to try, best first, for an impostor invoked as `invoked_name`.
+/// `env` looks up environment variables.
+pub(crate) fn candidates(
+    invoked_name: &str,
+    wrapper_name: &str,
+    env: impl Fn(&str) -> Option<String>,
+) -> Vec<Candidate> {
+    if invoked_name == wrapper_name {
+        return vec![Candidate::new("cc")];
+    }
+    let prefixes = env("WILD_PROXY_STRIP_PREFIXES");
+    let name = prefixes
+        .as_deref()
+        .unwrap_or(DEFAULT_STRIP_PREFIXES)
+        .split(',')
+        .filter(|prefix| !prefix.is_empty())
+        .find_map(|prefix| invoked_name.strip_prefix(prefix))
+        .filter(|name| !name.is_empty())
+        .unwrap_or(invoked_name);
+
+    if is_linker_name(name) {
+        let mut candidates = vec![Candidate::new(name)];
+        // There's no real `ld.wild`, and the host's triplet-prefixed `ld` is just `ld`.
+        if let Some(unsuffixed) = name.strip_suffix(".wild") {
+            candidates.push(Candidate::new(unsuffixed));
+        }
+        if let Some((triplet, _)) = name.rsplit_once('-')
+            && is_host_triplet(triplet)
+        {
+            candidates.push(Candidate::new("ld"));
+        }
+        return candidates;
+    }
+    let Some(parsed) = DriverName::parse(name) else {
+        return vec![Candidate::new(name)];
+    };
+    let override_var = if C_DRIVERS.contains(&parsed.driver) {
+        Some("WILD_PROXY_CC")
+    } else if CXX_DRIVERS.contains(&parsed.driver) {
+        Some("WILD_PROXY_CXX")
+    } else {
+        None
+    };
+    if let Some(program) = override_var
+        .and_then(env)
+        .filter(|program| !program.is_empty())
+    {
+        return vec![Candidate::new(program)];
+    }
+
+    let mut candidates = vec![Candidate::new(name)];
+    let versioned = |driver: &str, prefix_args: Vec<String>| {
+        let mut found = Vec::new();
+        if let Some(version) = parsed.version {
+            found.push(Candidate {
+                name: format!("{driver}-{version}").into(),
+                prefix_args: prefix_args.clone(),
+                version: None,
+            });
+        }
+        found.push(Candidate {
+            name: driver.into(),
+            prefix_args,
+            version: parsed.version.map(str::to_owned),
+        });
+        found
+    };
+    match parsed.triplet {
+        None => {
+            if parsed.version.is_some() {
+                candidates.extend(versioned(parsed.driver, Vec::new()).into_iter().skip(1));
+            }
+        }
+        Some(triplet) => {
+            if parsed.version.is_some() {
+                let with_triplet = format!("{triplet}-{}", parsed.driver);
+                candidates.extend(versioned(&with_triplet, Vec::new()).into_iter().skip(1));
+            }
+            if is_host_triplet(triplet) {
+                candidates.extend(versioned(parsed.driver, Vec::new()));
+            } else if parsed.driver.starts_with("clang") {
+                candidates.extend(versioned(
+                    parsed.driver,
+                    vec![format!("--target={triplet}")],
+                ));
+            }
+        }
+    }
+    candidates
+}
+
+/// Returns whether `name` is a linker we want an impostor for, like `ld.bfd` or
+/// `x86_64-linux-gnu-ld`.
+pub(crate) fn is_linker_name(name: &str) -> bool {
+    LINKERS.iter().any(|linker| {
+        name == *linker
+            || name
+                .strip_suffix(linker)
+                .and_then(|prefix| prefix.strip_suffix('-'))
+                .is_some_and(is_linux_triplet)
+    })
+}
+
+/// Returns whether the first argument given to `wild-proxy` names a compiler or a compiler
+/// launcher rather than being a compiler argument like `foo.c` or `-O2`.
+pub(crate) fn is_compiler(arg: &str) -> bool {
+    if arg.starts_with('-') {
+        return false;
+    }
+    let name = arg.rsplit('/').next().unwrap_or(arg);
+    if DriverName::parse(name).is_some() || is_compiler_wrapper(name) {
+        return true;
+    }
+    // Any other executable given by path, like a MinGW cross compiler, but not something like
+    // `./foo.o` that happens to have the executable bit set.
+    arg.contains('/')
+        && !name.contains('.')
+        && std::fs::metadata(arg)
+            .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+/// Returns whether `program`, a name or a path, is a compiler wrapper like ccache.
+pub(crate) fn is_compiler_wrapper(program: &str) -> bool {
+    let name = program.rsplit('/').next().unwrap_or(program);
+    COMPILER_WRAPPERS.contains(&name)
+}
+
+/// Returns whether `reported`, as printed by `-dumpfullversion` or `-dumpversion`, is `wanted` or
+/// a more specific version of it.
+pub(crate) fn version_matches(reported: &str, wanted: &str) -> bool {
+    reported
+        .trim()
+        .strip_prefix(wanted)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+}
+
+/// Splits a version suffix like `-14` or `-14.2`.
+fn split_version(name: &str) -> (&str, Option<&str>) {
+    match name.rsplit_once('-') {
+        Some((base, version))
+            if !version.is_empty()
+                && version
+                    .split('.')
+                    .all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit())) =>
+        {
+            (base, Some(version))
+        }
+        _ => (name, None),
+    }
+}
+
+/// Only Linux targets are interesting, since Wild produces ELF binaries.
+fn is_linux_triplet(triplet: &str) -> bool {
+    triplet.contains('-')
+        && triplet.contains("linux")
+        && triplet
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-' || b == b'.')
+}
+
+fn is_host_triplet(triplet: &str) -> bool {
+    let arch = triplet.split('-').next().unwrap_or_default();
+    match std::env::consts::ARCH {
+        "x86" => matches!(arch, "i386" | "i486" | "i586" | "i686"),
+        host => arch == host,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn resolve(invoked_name: &str, vars: &[(&str, &str)]) -> Vec<(String, String, Option<String>)> {
+        candidates(invoked_name, "wild-proxy", |name| {
+            vars.iter()
+                .find(|(var, _)| *var == name)
+                .map(|(_, value)| value.to_string())
+        })
+        .into_iter()
+        .map(|candidate| {
+            (
+                candidate.name.into_string().unwrap(),
+                candidate.prefix_args.join(" "),
+                candidate.version,
+            )
+        })
+        .collect()
+    }
+
+    fn plain(name: &str) -> (String, String, Option<String>) {
+        (name.to_owned(), String::new(), None)
+    }
+
+    #[test]
+    fn compiler_names() {
+        for name in [
+            "cc",
+            "c++",
+            "gcc",
+            "gcc-14",
+            "g++-14.2",
+            "clang",
+            "clang++-19",
+            "gfortran",
+            "x86_64-linux-gnu-gcc",
+            "x86_64-pc-linux-gnu-g++",
+            "aarch64-linux-gnu-gcc-14",
+            "riscv64-linux-gnu-cpp",
+        ] {
+            assert!(DriverName::parse(name).is_some(), "{name}");
+        }
+        for name in [
+            "gcc-ar",
+            "gcc-ar-14",
+            "x86_64-linux-gnu-gcc-ar-12",
+            "clang-format",
+            "clang-format-19",
+            "c++filt",
+            "llvm-gcc",
+            "x86_64-w64-mingw32-gcc",
+            "gcc-",
+            "ld",
+        ] {
+            assert!(DriverName::parse(name).is_none(), "{name}");
+        }
+        assert_eq!(
+            Some(DriverName {
+                triplet: Some("aarch64-linux-gnu"),
+                driver: "g++",
+                version: Some("14.2"),
+            }),
+            DriverName::parse("aarch64-linux-gnu-g++-14.2")
+        );
+    }
+
+    #[test]
+    fn plain_names() {
+        assert_eq!(vec![plain("cc")], resolve("wild-proxy", &[]));
+        assert_eq!(vec![plain("gcc")], resolve("gcc", &[]));
+        assert_eq!(vec![plain("clang++")], resolve("clang++", &[]));
+        assert_eq!(vec![plain("my-compiler")], resolve("my-compiler", &[]));
+    }
+
+    #[test]
+    fn versioned_names() {
+        assert_eq!(
+            vec![
+                plain("gcc-14"),
+                ("gcc".to_owned(), String::new(), Some("14".to_owned()))
+            ],
+            resolve("gcc-14", &[])
+        );
+        assert_eq!(
+            vec![
+                plain("g++-14.2"),
+                ("g++".to_owned(), String::new(), Some("14.2".to_owned()))
+            ],
+            resolve("g++-14.2", &[])
+        );
+    }
+
+    #[test]
+    fn prefixed_names() {
+        let host = format!("{}-linux-gnu", std::env::consts::ARCH);
+        assert_eq!(
+            vec![plain(&format!("{host}-gcc")), plain("gcc")],
+            resolve(&format!("wild-{host}-gcc"), &[])
+        );
+        assert_eq!(vec![plain("clang")], resolve("wild-clang", &[]));
+        assert_eq!(
+            vec![plain("g++")],
+            resolve("my-g++", &[("WILD_PROXY_STRIP_PREFIXES", "foo-,my-")])
+        );
+        assert_eq!(
+            vec![plain("wild-gcc")],
+            resolve("wild-gcc", &[("WILD_PROXY_STRIP_PREFIXES", "")])
+        );
+    }
+
+    #[test]
+    fn host_triplet_names() {
+        let host = format!("{}-linux-gnu", std::env::consts::ARCH);
+        assert_eq!(
+            vec![
+                plain(&format!("{host}-gcc-14")),
+                (format!("{host}-gcc"), String::new(), Some("14".to_owned())),
+                plain("gcc-14"),
+                ("gcc".to_owned(), String::new(), Some("14".to_owned())),
+            ],
+            resolve(&format!("{host}-gcc-14"), &[])
+        );
+    }
+
+    #[test]
+    fn foreign_triplet_names() {
+        let foreign = if std::env::consts::ARCH == "riscv64" {
+            "aarch64-linux-gnu"
+        } else {
+            "riscv64-linux-gnu"
+        };
+        // A cross GCC can't be replaced by the host one.
+        assert_eq!(
+            vec![plain(&format!("{foreign}-gcc"))],
+            resolve(&format!("{foreign}-gcc"), &[])
+        );
+        let target = format!("--target={foreign}");
+        assert_eq!(
+            vec![
+                plain(&format!("{foreign}-clang++-19")),
+                (
+                    format!("{foreign}-clang++"),
+                    String::new(),
+                    Some("19".to_owned())
+                ),
+                ("clang++-19".to_owned(), target.clone(), None),
+                ("clang++".to_owned(), target, Some("19".to_owned())),
+            ],
+            resolve(&format!("{foreign}-clang++-19"), &[])
+        );
+    }
+
+    #[test]
+    fn overrides() {
+        let vars = [
+            ("WILD_PROXY_CC", "/opt/gcc-15/bin/gcc"),
+            ("WILD_PROXY_CXX", "clang++-19"),
+        ];
+        for name in ["cc", "gcc-14", "clang", "x86_64-linux-gnu-gcc", "wild-c99"] {
+            assert_eq!(
+                vec![plain("/opt/gcc-15/bin/gcc")],
+                resolve(name, &vars),
+                "{name}"
+            );
+        }
+        for name in ["c++", "g++", "aarch64-linux-gnu-g++-12"] {
+            assert_eq!(vec![plain("clang++-19")], resolve(name, &vars), "{name}");
+        }
+        assert_eq!(vec![plain("cpp")], resolve("cpp", &vars));
+    }
+
+    #[test]
+    fn launcher_arguments() {
+        for arg in [
+            "gcc",
+            "clang++-19",
+            "/opt/gcc-15/bin/g++",
+            "ccache",
+            "/usr/bin/sccache",
+            "x86_64-linux-gnu-gcc",
+        ] {
+            assert!(is_compiler(arg), "{arg}");
+        }
+        for arg in [
+            "foo.c",
+            "hello.o",
+            "-O2",
+            "-o",
+            "libfoo.a",
+            "/usr/lib/libc.so.6",
+        ] {
+            assert!(!is_compiler(arg), "{arg}");
+        }
+    }
+
+    #[test]
+    fn compiler_wrappers() {
+        for program in ["ccache", "/usr/bin/sccache", "distcc", "/usr/bin/icecc"] {
+            assert!(is_compiler_wrapper(program), "{program}");
+        }
+        for program in ["gcc", "/usr/lib/ccache/gcc", "ccache-swig"] {
+            assert!(!is_compiler_wrapper(program), "{program}");
+        }
+    }
+
+    #[test]
+    fn linker_names() {
+        for name in [
+            "ld",
+            "ld.bfd",
+            "ld.wild",
+            "x86_64-linux-gnu-ld",
+            "aarch64-linux-gnu-ld.bfd",
+        ] {
+            assert!(is_linker_name(name), "{name}");
+        }
+        for name in [
+            "ld.gold",
+            "ld.so",
+            "ldd",
+            "gold",
+            "x86_64-w64-mingw32-ld",
+            "gcc",
+        ] {
+            assert!(!is_linker_name(name), "{name}");
+        }
+
+        let host = format!("{}-linux-gnu", std::env::consts::ARCH);
+        assert_eq!(vec![plain("ld")], resolve("ld", &[]));
+        assert_eq!(vec![plain("ld.bfd")], resolve("ld.bfd", &[]));
+        assert_eq!(vec![plain("ld.wild"), plain("ld")], resolve("ld.wild", &[]));
+        assert_eq!(
+            vec![plain(&format!("{host}-ld")), plain("ld")],
+            resolve(&format!("{host}-ld"), &[])
+        );
+        assert_eq!(
+            vec![
+                plain("riscv64-linux-gnu-ld.wild"),
+                plain("riscv64-linux-gnu-ld")
+            ],
+            resolve("riscv64-linux-gnu-ld.wild", &[])
+        );
+    }
+
+    #[test]
+    fn versions() {
+        assert!(version_matches("14.2.0\n", "14"));
+        assert!(version_matches("14.2.0", "14.2"));
+        assert!(version_matches("19", "19"));
+        assert!(!version_matches("14.2.0", "1"));
+        assert!(!version_matches("14.2.0", "14.3"));
+        assert!(!version_matches("", "14"));
+    }
+}
@@ -0,0 +1,203 @@
+//! Directories of cached build outputs, e.g. links in `WILD_PROXY_LINK_CACHE`. Entries are
+//! restored with a reflink or a copy, and the least recently used ones are evicted once a directory
+//! grows past its size limit.
+
+use anyhow::{Context, Result, bail};
+use std::{
+    fs::File,
+    os::{fd::AsRawFd, unix::ffi::OsStrExt},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+const DEFAULT_MAX_SIZE: u64 = 5 << 30;
+
+pub(crate) struct OutputCache {
+    dir: PathBuf,
+    max_size: u64,
+}
+
+impl OutputCache {
+    /// Returns the cache in the directory named by `dir_env`, if it's set, limited to the size in
+    /// `size_env`.
+    pub(crate) fn from_env(dir_env: &str, size_env: &str) -> Result<Option<Self>> {
+        let Some(dir) = std::env::var_os(dir_env).filter(|dir| !dir.is_empty()) else {
+            return Ok(None);
+        };
+        let max_size = match std::env::var(size_env) {
+            Ok(size) => parse_size(&size).with_context(|| format!("Invalid {size_env}"))?,
+            Err(_) => DEFAULT_MAX_SIZE,
+        };
+        Ok(Some(Self::new(dir.into(), max_size)))
+    }
+
+    pub(crate) fn new(dir: PathBuf, max_size: u64) -> Self {
+        Self { dir, max_size }
+    }
+
+    /// Restores the cached output for `key` to `output`. Returns whether there was one.
+    pub(crate) fn restore(&self, key: &str, output: &Path) -> bool {
+        let entry = self.dir.join(key);
+        if !entry.is_file() {
+            return false;
+        }
+        let _ = std::fs::remove_file(output);
+        // Never hardlinked, tools that change the output in place would change the entry as well.
+        let restored =
+            reflink(&entry, output).or_else(|_| std::fs::copy(&entry, output).map(|_| ()));
+        match restored {
+            Ok(()) => {
+                tracing::debug!("Restored {} from the cache", output.display());
+                // Marks the entry as recently used for eviction.
+                let _ = File::options()
+                    .write(true)
+                    .open(&entry)
+                    .and_then(|file| file.set_modified(SystemTime::now()));
+                true
+            }
+            Err(e) => {
+                tracing::warn!("Failed to restore {} from the cache: {e}", output.display());
+                false
+            }
+        }
+    }
+
+    /// Stores `output` under `key`. Failing to do so never fails the build.
+    pub(crate) fn store(&self, key: &str, output: &Path) {
+        if let Err(e) = self.store_entry(key, output) {
+            tracing::warn!("Failed to store {} in the cache: {e:?}", output.display());
+            return;
+        }
+        if let Err(e) = self.evict() {
+            tracing::warn!("Failed to evict from the cache: {e:?}");
+        }
+    }
+
+    fn store_entry(&self, key: &str, output: &Path) -> Result<()> {
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create {}", self.dir.display()))?;
+        // Written under a temporary name first, so concurrent links never see half an entry.
+        let temporary = self.dir.join(format!(".{key}.{}", std::process::id()));
+        let _ = std::fs::remove_file(&temporary);
+        reflink(output, &temporary)
+            .or_else(|_| std::fs::copy(output, &temporary).map(|_| ()))
+            .with_context(|| format!("Failed to copy {}", output.display()))?;
+        std::fs::rename(&temporary, self.dir.join(key))?;
+        Ok(())
+    }
+
+    /// Removes the least recently used entries until the cache fits into its size.
+    fn evict(&self) -> Result<()> {
+        let mut entries = Vec::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if metadata.is_file() && !entry.file_name().as_bytes().starts_with(b".") {
+                entries.push((metadata.modified()?, metadata.len(), entry.path()));
+            }
+        }
+        let mut size = entries.iter().map(|(_, len, _)| len).sum::<u64>();
+        entries.sort();
+        for (_, len, path) in entries {
+            if size <= self.max_size {
+                break;
+            }
+            std::fs::remove_file(&path)?;
+            tracing::debug!("Evicted {} from the cache", path.display());
+            size -= len;
+        }
+        Ok(())
+    }
+}
+
+/// Adds `bytes` with their length, so adjacent values can't run into each other.
+pub(crate) fn add(hasher: &mut blake3::Hasher, bytes: &[u8]) {
+    hasher.update(&(bytes.len() as u64).to_le_bytes());
+    hasher.update(bytes);
+}
+
+/// Clones `from` to the new file `to` on filesystems that support it.
+fn reflink(from: &Path, to: &Path) -> std::io::Result<()> {
+    let source = File::open(from)?;
+    let destination = File::create_new(to)?;
+    // SAFETY: Both file descriptors stay open for the duration of the call.
+    let result = unsafe { libc::ioctl(destination.as_raw_fd(), libc::FICLONE, source.as_raw_fd()) };
+    if result != 0 {
+        let error = std::io::Error::last_os_error();
+        drop(destination);
+        let _ = std::fs::remove_file(to);
+        return Err(error);
+    }
+    destination.set_permissions(source.metadata()?.permissions())
+}
+
+fn parse_size(size: &str) -> Result<u64> {
+    let size = size.trim();
+    let (number, unit) = size
+        .find(|c: char| !c.is_ascii_digit())
+        .map_or((size, ""), |index| size.split_at(index));
+    let shift = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 0,
+        "K" | "KB" | "KIB" => 10,
+        "M" | "MB" | "MIB" => 20,
+        "G" | "GB" | "GIB" => 30,
+        other => bail!("Unknown size unit `{other}` in `{size}`"),
+    };
+    let number = number
+        .parse::<u64>()
+        .with_context(|| format!("Invalid size `{size}`"))?;
+    number
+        .checked_mul(1 << shift)
+        .with_context(|| format!("Size `{size}` is too large"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn sizes() {
+        assert_eq!(512, parse_size("512").unwrap());
+        assert_eq!(10 << 20, parse_size("10M").unwrap());
+        assert_eq!(2 << 30, parse_size("2 GiB").unwrap());
+        assert!(parse_size("2T").is_err());
+        assert!(parse_size("lots").is_err());
+    }
+
+    #[test]
+    fn restore_and_evict() {
+        let dir =
+            std::env::temp_dir().join(format!("wild-proxy-output-cache-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cache = OutputCache::new(dir.join("cache"), 10);
+        let output = dir.join("out");
+        std::fs::write(&output, "123456").unwrap();
+        std::fs::set_permissions(&output, std::os::unix::fs::PermissionsExt::from_mode(0o755))
+            .unwrap();
+        cache.store("a", &output);
+        std::fs::remove_file(&output).unwrap();
+        assert!(!cache.restore("b", &output));
+        assert!(cache.restore("a", &output));
+        assert_eq!("123456", std::fs::read_to_string(&output).unwrap());
+        let mode =
+            std::os::unix::fs::PermissionsExt::mode(&output.metadata().unwrap().permissions());
+        assert_eq!(0o755, mode & 0o777);
+
+        // Changing the output in place leaves the entry alone.
+        std::fs::write(&output, "7890").unwrap();
+        assert_eq!(
+            "123456",
+            std::fs::read_to_string(cache.dir.join("a")).unwrap()
+        );
+
+        // Over the size now, the older entry goes.
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        std::fs::write(dir.join("other"), "abcdef").unwrap();
+        cache.store("b", &dir.join("other"));
+        assert!(!cache.dir.join("a").exists());
+        assert!(cache.dir.join("b").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,263 @@
+//! Running the compiler with `-###` and making sense of everything it prints besides the commands.
+//!
+//! The probe runs in the C locale, so the output can be parsed regardless of the user's language.
+//! Diagnostics are shown in the user's locale by running the probe again without the override and
+//! taking the lines at the same positions.
+
+use anyhow::{Context, Result};
+use std::{
+    ffi::{OsStr, OsString},
+    io::Write,
+    path::Path,
+    process::{Command, Output, exit},
+};
+
+/// Lines the drivers are known to print around the commands, in the C locale. Other lines are
+/// ignored as well, with a warning for `-v`.
+const INFO_PREFIXES: &[&str] = &[
+    // GCC
+    "Using built-in specs.",
+    "Reading specs from ",
+    "COLLECT_GCC=",
+    "COLLECT_LTO_WRAPPER=",
+    "COLLECT_GCC_OPTIONS=",
+    "COLLECT_AS_OPTIONS=",
+    // Printed when GCC drops a jobserver it can't use, e.g. from a make recipe without `+`.
+    "MAKEFLAGS=",
+    "COMPILER_PATH=",
+    "LIBRARY_PATH=",
+    "OFFLOAD_TARGET_NAMES=",
+    "OFFLOAD_TARGET_DEFAULT=",
+    "Configured with: ",
+    "Supported LTO compression algorithms: ",
+    "gcc version ",
+    "Driving: ",
+    // Clang
+    "InstalledDir: ",
+    "Build config: ",
+    "Configuration file: ",
+    "System configuration file directory: ",
+    "User configuration file directory: ",
+    "Found candidate GCC installation: ",
+    "Selected GCC installation: ",
+    "Candidate multilib: ",
+    "Selected multilib: ",
+    "Found CUDA installation: ",
+    "Found HIP installation: ",
+    // Both
+    "Target: ",
+    "Thread model: ",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LineKind {
+    Command,
+    Info,
+    Warning,
+    Error,
+    Unknown,
+}
+
+/// Paths in the dump are bytes, but everything we look for is ASCII.
+fn classify(line: &[u8]) -> LineKind {
+    let line = String::from_utf8_lossy(line);
+    if line.trim().is_empty() || INFO_PREFIXES.iter().any(|prefix| line.starts_with(prefix)) {
+        LineKind::Info
+    } else if line.starts_with(' ') {
+        LineKind::Command
+    } else if line.contains("clang version ") {
+        // Possibly with a vendor prefix like `Debian clang version 14.0.6`.
+        LineKind::Info
+    } else if line.contains("error: ") {
+        LineKind::Error
+    } else if line.contains("warning: ") || line.contains("note: ") {
+        LineKind::Warning
+    } else {
+        LineKind::Unknown
+    }
+}
+
+/// Asks the compiler which commands it would run, without running them.
+pub(crate) fn run(compiler_path: &Path, args: &[OsString], chain: &OsStr) -> Result<Output> {
+    let mut command = probe_command(compiler_path, args, chain);
+    command.env("LC_ALL", "C").env_remove("LANGUAGE");
+    command
+        .output()
+        .with_context(|| format!("Failed to run {}", compiler_path.display()))
+}
+
+/// `chain` is the value of [`CHAIN_ENV`](crate::recursion::CHAIN_ENV) for the compiler.
+fn probe_command(compiler_path: &Path, args: &[OsString], chain: &OsStr) -> Command {
+    let mut command = Command::new(compiler_path);
+    command.args(args).env(crate::recursion::CHAIN_ENV, chain);
+    if !args.iter().any(|arg| arg == "-###") {
+        command.arg("-###");
+    }
+    command
+}
+
+/// Shows the diagnostics from the probe and exits like the compiler would if there are errors.
+/// GCC exits with 0 for `-### -wrong-arg`, so the exit code alone isn't enough. Returns the dump,
+/// with lines we don't understand shown if `verbose`.
+pub(crate) fn check(
+    compiler_path: &Path,
+    args: &[OsString],
+    chain: &OsStr,
+    output: Output,
+    verbose: bool,
+) -> Result<Vec<u8>> {
+    let dump = output.stderr;
+    let kinds = lines(&dump).map(classify).collect::<Vec<_>>();
+    let failed = !output.status.success() || kinds.contains(&LineKind::Error);
+    let shown = kinds
+        .iter()
+        .enumerate()
+        .filter(|(_, kind)| match kind {
+            LineKind::Warning | LineKind::Error => true,
+            // Anything else printed by a failing probe, e.g. `compilation terminated.` or a nested
+            // proxy giving up.
+            LineKind::Unknown => failed,
+            LineKind::Command | LineKind::Info => false,
+        })
+        .map(|(index, _)| index)
+        .collect::<Vec<_>>();
+    if !shown.is_empty() {
+        let localized = (!user_locale_is_c())
+            .then(|| probe_command(compiler_path, args, chain).output().ok())
+            .flatten()
+            .map(|output| output.stderr);
+        let mut stderr = std::io::stderr().lock();
+        for line in localized_lines(&dump, localized.as_deref(), &shown) {
+            stderr.write_all(line.trim_ascii_end())?;
+            stderr.write_all(b"\n")?;
+        }
+    }
+    if failed {
+        exit(output.status.code().filter(|code| *code != 0).unwrap_or(1));
+    }
+
+    if verbose {
+        for (line, _) in lines(&dump)
+            .zip(&kinds)
+            .filter(|(_, kind)| **kind == LineKind::Unknown)
+        {
+            eprintln!(
+                "wild-proxy: warning: ignoring unexpected output from `{} -###`: {}",
+                compiler_path.display(),
+                String::from_utf8_lossy(line)
+            );
+        }
+    }
+    Ok(dump)
+}
+
+/// Splits the dump into lines like [`str::lines`].
+pub(crate) fn lines(dump: &[u8]) -> impl Iterator<Item = &[u8]> {
+    dump.split_inclusive(|byte| *byte == b'\n').map(|line| {
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        line.strip_suffix(b"\r").unwrap_or(line)
+    })
+}
+
+/// Returns the target triple the compiler reports in the dump.
+pub(crate) fn target(dump: &[u8]) -> Option<String> {
+    lines(dump).find_map(|line| {
+        let target = line.strip_prefix(b"Target: ")?;
+        Some(String::from_utf8_lossy(target).trim().to_owned())
+    })
+}
+
+/// Returns the lines at `shown` from the localized output if it lines up with the C locale one.
+fn localized_lines<'a>(
+    dump: &'a [u8],
+    localized: Option<&'a [u8]>,
+    shown: &[usize],
+) -> Vec<&'a [u8]> {
+    let dump_lines = lines(dump).collect::<Vec<_>>();
+    let lines = match localized.map(|localized| lines(localized).collect::<Vec<_>>()) {
+        Some(localized_lines) if localized_lines.len() == dump_lines.len() => localized_lines,
+        _ => dump_lines,
+    };
+    shown.iter().map(|&index| lines[index]).collect()
+}
+
+fn user_locale_is_c() -> bool {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .find_map(|var| std::env::var(var).ok().filter(|value| !value.is_empty()))
+        .is_none_or(|locale| locale == "C" || locale == "POSIX")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn classify_lines() {
+        let input = r#"Using built-in specs.
+COLLECT_GCC=gcc
+Target: x86_64-linux-gnu
+Thread model: posix
+gcc version 12.2.0 (Debian 12.2.0-14+deb12u1)
+gcc: warning: '-x c' after last input file has no effect
+gcc: error: unrecognized command-line option '-wrong-arg'
+COLLECT_GCC_OPTIONS='-wrong-arg' '-mtune=generic' '-march=x86-64'
+COLLECT_AS_OPTIONS='--noexecstack'
+MAKEFLAGS=s -j2 
+ /usr/lib/gcc/x86_64-linux-gnu/12/cc1 -quiet hello.c
+Debian clang version 14.0.6
+InstalledDir: /usr/bin
+ (in-process)
+Verwendung interner Spezifikationen.
+"#;
+        assert_eq!(
+            vec![
+                LineKind::Info,
+                LineKind::Info,
+                LineKind::Info,
+                LineKind::Info,
+                LineKind::Info,
+                LineKind::Warning,
+                LineKind::Error,
+                LineKind::Info,
+                LineKind::Info,
+                LineKind::Info,
+                LineKind::Command,
+                LineKind::Info,
+                LineKind::Info,
+                LineKind::Command,
+                LineKind::Unknown,
+            ],
+            lines(input.as_bytes()).map(classify).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn localized_diagnostics() {
+        let dump = b"Using built-in specs.\ngcc: error: unrecognized command-line option '-x'\n";
+        let localized = "Verwendung interner Spezifikationen.\ngcc: Fehler: nicht erkannte Kommandozeilenoption »-x«\n";
+        assert_eq!(
+            vec!["gcc: Fehler: nicht erkannte Kommandozeilenoption »-x«".as_bytes()],
+            localized_lines(dump, Some(localized.as_bytes()), &[1])
+        );
+        // Falls back to the C locale if the outputs don't line up.
+        assert_eq!(
+            vec![b"gcc: error: unrecognized command-line option '-x'".as_slice()],
+            localized_lines(dump, Some(b"Fehler\n"), &[1])
+        );
+        assert_eq!(
+            vec![b"gcc: error: unrecognized command-line option '-x'".as_slice()],
+            localized_lines(dump, None, &[1])
+        );
+        // Latin-1 paths
+        assert_eq!(
+            vec![b"gcc: error: caf\xe9.c: No such file or directory".as_slice()],
+            localized_lines(
+                b"gcc: error: caf\xe9.c: No such file or directory\n",
+                None,
+                &[0]
+            )
+        );
+    }
+}
@@ -0,0 +1,120 @@
+//! Protection against the proxy invoking itself. Hard links, copies, symlink chains or shell
+//! wrappers that call `cc` again would otherwise make us delegate to ourselves forever.
+//!
+//! Every proxy passes `WILD_PROXY_CHAIN` with its own path and the compiler it delegates to on to
+//! the programs it runs. A nested proxy sees the marker, skips everything already in the chain and
+//! goes straight to the real compiler.
+
+use anyhow::{Result, bail};
+use std::{
+    ffi::OsString,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+
+pub(crate) const CHAIN_ENV: &str = "WILD_PROXY_CHAIN";
+
+/// More nested proxies than this can only be a loop.
+const MAX_DEPTH: usize = 8;
+
+/// Identifies a file regardless of the path used to reach it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct FileId {
+    dev: u64,
+    ino: u64,
+}
+
+impl FileId {
+    pub(crate) fn of(path: &Path) -> Option<Self> {
+        let meta = std::fs::metadata(path).ok()?;
+        Some(Self {
+            dev: meta.dev(),
+            ino: meta.ino(),
+        })
+    }
+}
+
+/// Paths of the proxies and compilers that led to this invocation, empty if we weren't invoked by
+/// another proxy.
+pub(crate) fn chain() -> Result<Vec<PathBuf>> {
+    let Some(value) = std::env::var_os(CHAIN_ENV).filter(|value| !value.is_empty()) else {
+        return Ok(Vec::new());
+    };
+    let chain = std::env::split_paths(&value).collect::<Vec<_>>();
+    if chain.len() >= MAX_DEPTH * 2 {
+        bail!(
+            "wild-proxy invoked itself recursively, giving up after {MAX_DEPTH} levels: {}",
+            value.display()
+        );
+    }
+    Ok(chain)
+}
+
+/// Returns the value of [`CHAIN_ENV`] for child processes, the chain extended with this proxy and
+/// the compiler it delegates to. The process environment is left alone, it's set on each child.
+pub(crate) fn extend_chain(mut chain: Vec<PathBuf>, compiler: &Path) -> Result<OsString> {
+    let real_exe = std::env::current_exe().and_then(|exe| exe.canonicalize())?;
+    chain.push(real_exe);
+    chain.push(
+        compiler
+            .canonicalize()
+            .unwrap_or_else(|_| compiler.to_owned()),
+    );
+    Ok(std::env::join_paths(chain)?)
+}
+
+/// Returns why `candidate` must not be used as the real compiler, if it must not.
+pub(crate) fn skip_reason(
+    candidate: &Path,
+    own_id: FileId,
+    chain: &[PathBuf],
+) -> Option<&'static str> {
+    if FileId::of(candidate) == Some(own_id) {
+        return Some("this wrapper");
+    }
+    let canonical = candidate.canonicalize().ok()?;
+    if chain
+        .iter()
+        .any(|link| *link == canonical || FileId::of(link) == FileId::of(&canonical))
+    {
+        return Some("already in the proxy chain");
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn detects_aliases_of_wrapper() {
+        let dir =
+            std::env::temp_dir().join(format!("wild-proxy-recursion-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let wrapper = dir.join("wild-proxy");
+        std::fs::write(&wrapper, "").unwrap();
+        let hard_link = dir.join("cc");
+        std::fs::hard_link(&wrapper, &hard_link).unwrap();
+        let symlink = dir.join("gcc");
+        std::os::unix::fs::symlink(&hard_link, &symlink).unwrap();
+        let chained_symlink = dir.join("g++");
+        std::os::unix::fs::symlink(&symlink, &chained_symlink).unwrap();
+        let shell_wrapper = dir.join("clang");
+        std::fs::write(&shell_wrapper, "#!/bin/sh\nexec cc \"$@\"\n").unwrap();
+        let real_compiler = dir.join("real-cc");
+        std::fs::write(&real_compiler, "").unwrap();
+
+        let own_id = FileId::of(&wrapper).unwrap();
+        let chain = vec![shell_wrapper.canonicalize().unwrap()];
+        for alias in [&wrapper, &hard_link, &symlink, &chained_symlink] {
+            assert_eq!(Some("this wrapper"), skip_reason(alias, own_id, &chain));
+        }
+        assert_eq!(
+            Some("already in the proxy chain"),
+            skip_reason(&shell_wrapper, own_id, &chain)
+        );
+        assert_eq!(None, skip_reason(&real_compiler, own_id, &chain));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,414 @@
+//! Reproducer bundles for failed links. When `WILD_PROXY_REPRO_DIR` is set and Wild fails or
+//! panics, every input of the link is packed into a tarball together with the rewritten link
+//! arguments, so the link can be replayed with `wild-proxy replay <bundle>` on another machine.
+
+use anyhow::{Context, Result, bail};
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fs::File,
+    io::{BufWriter, Read, Write},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+const REPRO_DIR_ENV: &str = "WILD_PROXY_REPRO_DIR";
+const BUNDLE_ROOT: &str = "wild-repro";
+const ARGV_FILE: &str = "argv";
+const LINK_ARGS_FILE: &str = "link-args";
+const ERROR_FILE: &str = "error.txt";
+const NOTES_FILE: &str = "notes.txt";
+const OUTPUT_DIR: &str = "output";
+
+/// Linker options whose value is passed as the next argument. Their values must not be mistaken
+/// for input files, e.g. the dynamic linker path.
+const OPTIONS_WITH_VALUE: &[&str] = &[
+    "-m",
+    "-z",
+    "-e",
+    "-u",
+    "-h",
+    "-y",
+    "-R",
+    "-soname",
+    "-rpath",
+    "-rpath-link",
+    "-dynamic-linker",
+    "--dynamic-linker",
+    "-plugin",
+    "--plugin",
+    "-Map",
+    "--hash-style",
+    "--defsym",
+    "--wrap",
+    "--exclude-libs",
+    "--entry",
+    "--undefined",
+];
+
+pub(crate) fn repro_dir() -> Option<PathBuf> {
+    std::env::var_os(REPRO_DIR_ENV)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Writes a reproducer for the link described by `link_args` into `dir` and returns the path of
+/// the bundle.
+pub(crate) fn write_bundle(
+    dir: &Path,
+    driver_argv: &[String],
+    link_args: &[String],
+    error: &str,
+) -> Result<PathBuf> {
+    let mut bundle = Bundle::default();
+    let rewritten = bundle.rewrite_args(link_args)?;
+
+    let output_name = link_args
+        .windows(2)
+        .find_map(|window| (window[0] == "-o").then(|| window[1].as_str()))
+        .and_then(|output| Path::new(output).file_name())
+        .map_or_else(|| "a.out".into(), |name| name.to_string_lossy());
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create `{}`", dir.display()))?;
+    let bundle_path = dir.join(format!(
+        "wild-repro-{output_name}-{}.tar",
+        std::process::id()
+    ));
+
+    let file = File::create(&bundle_path)
+        .with_context(|| format!("Failed to create `{}`", bundle_path.display()))?;
+    let mut builder = tar::Builder::new(BufWriter::new(file));
+    let mut append = |name: &str, mode: u32, contents: &[u8]| {
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(mode & 0o7777);
+        header.set_size(contents.len() as u64);
+        builder.append_data(&mut header, format!("{BUNDLE_ROOT}/{name}"), contents)
+    };
+    append(
+        ARGV_FILE,
+        0o644,
+        (shell_words::join(driver_argv) + "\n").as_bytes(),
+    )?;
+    append(
+        LINK_ARGS_FILE,
+        0o644,
+        (shell_words::join(&rewritten) + "\n").as_bytes(),
+    )?;
+    append(ERROR_FILE, 0o644, format!("{error}\n").as_bytes())?;
+    if !bundle.notes.is_empty() {
+        append(
+            NOTES_FILE,
+            0o644,
+            (bundle.notes.join("\n") + "\n").as_bytes(),
+        )?;
+    }
+    for (name, contents, mode) in &bundle.files {
+        append(name, *mode, contents)?;
+    }
+    builder.into_inner()?.flush()?;
+
+    Ok(bundle_path)
+}
+
+/// Extracts a bundle written by [`write_bundle`] and runs the link again with Wild.
+pub fn replay(bundle_path: &Path) -> Result<()> {
+    let file = File::open(bundle_path)
+        .with_context(|| format!("Failed to open `{}`", bundle_path.display()))?;
+    let mut archive = tar::Archive::new(std::io::BufReader::new(file));
+
+    let extract_dir =
+        std::env::temp_dir().join(format!("wild-proxy-replay-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&extract_dir);
+    let result = replay_in(&extract_dir, &mut archive)
+        .with_context(|| format!("Failed to replay `{}`", bundle_path.display()));
+    let _ = std::fs::remove_dir_all(&extract_dir);
+    result
+}
+
+fn replay_in(extract_dir: &Path, archive: &mut tar::Archive<impl Read>) -> Result<()> {
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.into_owned();
+        if entry.header().entry_type() != tar::EntryType::Regular
+            || name
+                .components()
+                .any(|component| !matches!(component, std::path::Component::Normal(_)))
+        {
+            bail!("Refusing to extract `{}` from the bundle", name.display());
+        }
+        let mut contents = Vec::new();
+        entry.read_to_end(&mut contents)?;
+        let path = extract_dir.join(&name);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&path, &contents)
+            .with_context(|| format!("Failed to write `{}`", path.display()))?;
+        std::fs::set_permissions(
+            &path,
+            std::fs::Permissions::from_mode(entry.header().mode()?),
+        )?;
+    }
+
+    let root = extract_dir.join(BUNDLE_ROOT);
+    let link_args = std::fs::read_to_string(root.join(LINK_ARGS_FILE))
+        .context("Bundle does not contain link arguments")?;
+    let link_args = shell_words::split(&link_args)?;
+    std::fs::create_dir_all(root.join(OUTPUT_DIR))?;
+    let previous_dir = std::env::current_dir()?;
+    std::env::set_current_dir(&root)
+        .with_context(|| format!("Failed to enter `{}`", root.display()))?;
+
+    eprintln!("Replaying link in {}", root.display());
+    let result = crate::run_wild(
+        &link_args
+            .into_iter()
+            .map(OsString::from)
+            .collect::<Vec<_>>(),
+    );
+    // Leaves the directory, so it can be removed.
+    std::env::set_current_dir(previous_dir)?;
+    result
+}
+
+#[derive(Default)]
+struct Bundle {
+    /// Name inside the bundle root, contents and permission bits.
+    files: Vec<(String, Vec<u8>, u32)>,
+    /// Maps source paths to their names inside the bundle, so every file is stored only once.
+    names: HashMap<PathBuf, String>,
+    /// Library search paths of the link, needed to resolve libraries referenced by scripts.
+    search_dirs: Vec<PathBuf>,
+    /// What couldn't be bundled, for whoever replays the link.
+    notes: Vec<String>,
+}
+
+impl Bundle {
+    fn rewrite_args(&mut self, link_args: &[String]) -> Result<Vec<String>> {
+        self.search_dirs = link_args
+            .iter()
+            .enumerate()
+            .filter_map(|(i, arg)| match arg.strip_prefix("-L") {
+                Some("") => link_args.get(i + 1).map(PathBuf::from),
+                Some(dir) => Some(PathBuf::from(dir)),
+                None => None,
+            })
+            .collect::<Vec<_>>();
+
+        let mut rewritten = Vec::with_capacity(link_args.len());
+        let mut is_static = false;
+        let mut iter = link_args.iter();
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "-Bstatic" | "-static" | "-dn" | "-non_shared" => is_static = true,
+                "-Bdynamic" | "-dy" | "-call_shared" => is_static = false,
+                _ => {}
+            }
+
+            if arg == "-o" {
+                let output = iter.next().context("Missing value for `-o`")?;
+                let name = Path::new(output)
+                    .file_name()
+                    .map_or_else(|| "a.out".into(), |name| name.to_string_lossy());
+                rewritten.push(arg.clone());
+                rewritten.push(format!("{OUTPUT_DIR}/{name}"));
+            } else if arg == "-L" {
+                iter.next();
+            } else if arg.starts_with("-L") {
+                // All libraries get resolved and bundled, so search paths are no longer needed.
+            } else if arg == "-T" || arg == "--version-script" || arg == "--dynamic-list" {
+                let path = iter
+                    .next()
+                    .with_context(|| format!("Missing value for `{arg}`"))?;
+                rewritten.push(arg.clone());
+                rewritten.push(self.add_file(Path::new(path))?);
+            } else if let Some((flag, path)) = arg.split_once('=').filter(|(flag, _)| {
+                ["--script", "--version-script", "--dynamic-list"].contains(flag)
+            }) {
+                rewritten.push(format!("{flag}={}", self.add_file(Path::new(path))?));
+            } else if let Some(library) = arg.strip_prefix("-l") {
+                let library = if library.is_empty() {
+                    iter.next().context("Missing value for `-l`")?
+                } else {
+                    library
+                };
+                match resolve_library(library, &self.search_dirs, is_static) {
+                    Some(path) => rewritten.push(self.add_file(&path)?),
+                    None => {
+                        // Possibly why the link failed, the replay reports it too.
+                        self.notes.push(format!(
+                            "Library `{library}` was not found, `-l{library}` is passed as is"
+                        ));
+                        rewritten.push(format!("-l{library}"));
+                    }
+                }
+            } else if OPTIONS_WITH_VALUE.contains(&arg.as_str()) {
+                rewritten.push(arg.clone());
+                rewritten.extend(iter.next().cloned());
+            } else if !arg.starts_with('-') && Path::new(arg).is_file() {
+                rewritten.push(self.add_file(Path::new(arg))?);
+            } else {
+                rewritten.push(arg.clone());
+            }
+        }
+        Ok(rewritten)
+    }
+
+    /// Adds `path` to the bundle and returns its path relative to the bundle root. Linker scripts
+    /// are rewritten to refer to bundled copies of the files they reference.
+    fn add_file(&mut self, path: &Path) -> Result<String> {
+        if let Some(name) = self.names.get(path) {
+            return Ok(name.clone());
+        }
+        let mut contents =
+            std::fs::read(path).with_context(|| format!("Failed to read `{}`", path.display()))?;
+        let mode = std::fs::metadata(path)?.permissions().mode();
+        let file_name = path
+            .file_name()
+            .map_or_else(|| "input".into(), |name| name.to_string_lossy());
+        let name = format!("inputs/{}-{file_name}", self.names.len());
+        self.names.insert(path.to_owned(), name.clone());
+
+        if let Some(script) = as_linker_script(&contents) {
+            let script_dir = path.parent().unwrap_or(Path::new(""));
+            contents = self.rewrite_script(script, script_dir)?.into_bytes();
+        }
+        self.files.push((name.clone(), contents, mode));
+        Ok(name)
+    }
+
+    fn rewrite_script(&mut self, script: &str, script_dir: &Path) -> Result<String> {
+        let mut out = String::with_capacity(script.len());
+        let mut token_start = None;
+        for (i, c) in script.char_indices().chain([(script.len(), ' ')]) {
+            let is_separator = c.is_whitespace() || matches!(c, '(' | ')' | ',');
+            match (token_start, is_separator) {
+                (None, false) => token_start = Some(i),
+                (Some(start), true) => {
+                    let token = &script[start..i];
+                    match self.resolve_script_input(token, script_dir) {
+                        Some(path) => out.push_str(&self.add_file(&path)?),
+                        None => out.push_str(token),
+                    }
+                    token_start = None;
+                }
+                _ => {}
+            }
+            if is_separator && i < script.len() {
+                out.push(c);
+            }
+        }
+        Ok(out)
+    }
+
+    /// Resolves a token of a linker script to a file, if it names one. Like the linker, relative
+    /// names are looked up next to the script and then in the library search path.
+    fn resolve_script_input(&self, token: &str, script_dir: &Path) -> Option<PathBuf> {
+        if let Some(library) = token.strip_prefix("-l") {
+            return resolve_library(library, &self.search_dirs, false);
+        }
+        let path = Path::new(token);
+        if path.is_absolute() {
+            return path.is_file().then(|| path.to_owned());
+        }
+        if !token.contains('.') {
+            return None;
+        }
+        std::iter::once(script_dir)
+            .chain(self.search_dirs.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(path))
+            .find(|candidate| candidate.is_file())
+    }
+}
+
+/// Returns the contents as text if the file looks like a linker script rather than an object or an
+/// archive.
+fn as_linker_script(contents: &[u8]) -> Option<&str> {
+    if contents.starts_with(b"\x7fELF") || contents.starts_with(b"!<arch>") {
+        return None;
+    }
+    std::str::from_utf8(contents).ok()
+}
+
+fn resolve_library(library: &str, search_dirs: &[PathBuf], is_static: bool) -> Option<PathBuf> {
+    let candidates = if let Some(exact) = library.strip_prefix(':') {
+        vec![exact.to_owned()]
+    } else if is_static {
+        vec![format!("lib{library}.a")]
+    } else {
+        vec![format!("lib{library}.so"), format!("lib{library}.a")]
+    };
+    search_dirs.iter().find_map(|dir| {
+        candidates
+            .iter()
+            .map(|candidate| dir.join(candidate))
+            .find(|path| path.is_file())
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn bundle_rewrites_inputs() {
+        let dir =
+            std::env::temp_dir().join(format!("wild-proxy-repro-test-{}", std::process::id()));
+        let lib_dir = dir.join("lib");
+        std::fs::create_dir_all(&lib_dir).unwrap();
+        std::fs::write(dir.join("main.o"), b"\x7fELF").unwrap();
+        std::fs::write(lib_dir.join("libfoo.a"), b"!<arch>\n").unwrap();
+        std::fs::write(lib_dir.join("libc.so.6"), b"\x7fELF").unwrap();
+        let script = format!("GROUP ( {}/libc.so.6 )\n", lib_dir.display());
+        std::fs::write(lib_dir.join("libc.so"), &script).unwrap();
+
+        let args = [
+            "-dynamic-linker",
+            "/lib64/ld-linux-x86-64.so.2",
+            "-o",
+            "/somewhere/bin",
+            &format!("-L{}", lib_dir.display()),
+            &dir.join("main.o").to_string_lossy(),
+            "-Bstatic",
+            "-lfoo",
+            "-Bdynamic",
+            "-lc",
+            "-l",
+            "missing",
+        ]
+        .map(|s| s.to_string());
+        let mut bundle = Bundle::default();
+        let rewritten = bundle.rewrite_args(&args).unwrap();
+        assert_eq!(
+            vec![
+                "-dynamic-linker",
+                "/lib64/ld-linux-x86-64.so.2",
+                "-o",
+                "output/bin",
+                "inputs/0-main.o",
+                "-Bstatic",
+                "inputs/1-libfoo.a",
+                "-Bdynamic",
+                "inputs/2-libc.so",
+                "-lmissing",
+            ],
+            rewritten
+        );
+        assert_eq!(
+            vec!["Library `missing` was not found, `-lmissing` is passed as is"],
+            bundle.notes
+        );
+        let (_, libc_script, _) = bundle
+            .files
+            .iter()
+            .find(|(name, _, _)| name == "inputs/2-libc.so")
+            .unwrap();
+        assert_eq!(
+            "GROUP ( inputs/3-libc.so.6 )\n",
+            String::from_utf8_lossy(libc_script)
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,302 @@
+//! Rules that drop, replace or append arguments of Wild links, read from the file named by
+//! `WILD_PROXY_CONFIG`. Each rule is a section named after it, optionally scoped by globs for the
+//! target triple, the output and the compiler, and applied in order:
+//!
+//! ```ini
+//! [no-lto-plugin]
+//! compiler = gcc*
+//! drop = -plugin *
+//! drop = -plugin-opt=*
+//! replace = --hash-style=* => --hash-style=gnu
+//! append = --gc-sections
+//! ```
+//!
+//! Patterns are globs with `*` and `?`, several of them match consecutive arguments. Rules only
+//! change Wild's arguments, and everything that shows or reuses them gets the rewritten list: the
+//! link database, shadow links, the link cache, `doctor`, `-###` and reproducers. The system linker,
+//! whether as a fallback, the primary of a shadow link or picked with `-B`, always gets the link
+//! command of the compiler as is.
+
+use anyhow::{Context, Result, bail};
+use std::{ffi::OsString, path::Path};
+
+const CONFIG_ENV: &str = "WILD_PROXY_CONFIG";
+
+#[derive(Debug, Default)]
+pub(crate) struct Rules(Vec<Rule>);
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Rule {
+    name: String,
+    target: Option<String>,
+    output: Option<String>,
+    compiler: Option<String>,
+    actions: Vec<Action>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Action {
+    Drop(Vec<String>),
+    Replace(Vec<String>, Vec<String>),
+    Append(Vec<String>),
+}
+
+/// What rules are scoped by.
+pub(crate) struct Scope<'a> {
+    /// The `Target:` of the compiler dump.
+    pub(crate) target: Option<&'a str>,
+    pub(crate) output: Option<&'a Path>,
+    pub(crate) compiler: &'a Path,
+}
+
+impl Rules {
+    pub(crate) fn from_env() -> Result<Self> {
+        let Some(path) = std::env::var_os(CONFIG_ENV).filter(|path| !path.is_empty()) else {
+            return Ok(Self::default());
+        };
+        let config = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {CONFIG_ENV} `{}`", path.display()))?;
+        Self::parse(&config).with_context(|| format!("Invalid config `{}`", path.display()))
+    }
+
+    fn parse(config: &str) -> Result<Self> {
+        let mut rules = Vec::new();
+        for (number, line) in config.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(['#', ';']) {
+                continue;
+            }
+            let context = || format!("Line {}: `{line}`", number + 1);
+            if let Some(name) = line
+                .strip_prefix('[')
+                .and_then(|line| line.strip_suffix(']'))
+            {
+                rules.push(Rule {
+                    name: name.trim().to_owned(),
+                    ..Default::default()
+                });
+                continue;
+            }
+            let Some(rule) = rules.last_mut() else {
+                bail!("{}: rules start with a `[name]` line", context());
+            };
+            let Some((key, value)) = line.split_once('=') else {
+                bail!("{}: expected `key = value`", context());
+            };
+            let value = value.trim();
+            let words = |value: &str| {
+                let words = shell_words::split(value).with_context(context)?;
+                if words.is_empty() {
+                    bail!("{}: missing arguments", context());
+                }
+                Ok(words)
+            };
+            match key.trim() {
+                "target" => rule.target = Some(value.to_owned()),
+                "output" => rule.output = Some(value.to_owned()),
+                "compiler" => rule.compiler = Some(value.to_owned()),
+                "drop" => rule.actions.push(Action::Drop(words(value)?)),
+                "append" => rule.actions.push(Action::Append(words(value)?)),
+                "replace" => {
+                    let Some((pattern, replacement)) = value.split_once("=>") else {
+                        bail!(
+                            "{}: expected `replace = <pattern> => <arguments>`",
+                            context()
+                        );
+                    };
+                    let replacement = shell_words::split(replacement).with_context(context)?;
+                    rule.actions
+                        .push(Action::Replace(words(pattern)?, replacement));
+                }
+                other => bail!("{}: unknown key `{other}`", context()),
+            }
+        }
+        Ok(Self(rules))
+    }
+
+    /// Applies the rules in scope to Wild's arguments. Returns the new arguments and the names of
+    /// the rules that changed them.
+    pub(crate) fn apply(
+        &self,
+        mut args: Vec<OsString>,
+        scope: &Scope,
+    ) -> (Vec<OsString>, Vec<&str>) {
+        let mut applied = Vec::new();
+        for rule in self.0.iter().filter(|rule| rule.in_scope(scope)) {
+            let before = args.clone();
+            for action in &rule.actions {
+                match action {
+                    Action::Drop(pattern) => replace_all(&mut args, pattern, &[]),
+                    Action::Replace(pattern, replacement) => {
+                        replace_all(&mut args, pattern, replacement);
+                    }
+                    Action::Append(extra) => args.extend(extra.iter().map(OsString::from)),
+                }
+            }
+            if args != before {
+                applied.push(rule.name.as_str());
+            }
+        }
+        (args, applied)
+    }
+}
+
+impl Rule {
+    fn in_scope(&self, scope: &Scope) -> bool {
+        let matches_path = |pattern: &Option<String>, path: Option<&Path>| {
+            let Some(pattern) = pattern else {
+                return true;
+            };
+            path.is_some_and(|path| {
+                glob(pattern, &path.to_string_lossy())
+                    || path
+                        .file_name()
+                        .is_some_and(|name| glob(pattern, &name.to_string_lossy()))
+            })
+        };
+        self.target
+            .as_ref()
+            .is_none_or(|pattern| scope.target.is_some_and(|target| glob(pattern, target)))
+            && matches_path(&self.output, scope.output)
+            && matches_path(&self.compiler, Some(scope.compiler))
+    }
+}
+
+/// Replaces every run of arguments matching `pattern`.
+fn replace_all(args: &mut Vec<OsString>, pattern: &[String], replacement: &[String]) {
+    let mut index = 0;
+    while index + pattern.len() <= args.len() {
+        let matches = pattern
+            .iter()
+            .zip(&args[index..])
+            .all(|(pattern, arg)| arg.to_str().is_some_and(|arg| glob(pattern, arg)));
+        if matches {
+            args.splice(
+                index..index + pattern.len(),
+                replacement.iter().map(OsString::from),
+            );
+            index += replacement.len();
+        } else {
+            index += 1;
+        }
+    }
+}
+
+/// Matches `text` against a glob where `*` is any run of characters and `?` any single one.
+fn glob(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, start)) => {
+                    p = star + 1;
+                    t = start + 1;
+                    backtrack = Some((star, start + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const CONFIG: &str = r#"
+# Distro defaults Wild doesn't need.
+[no-lto-plugin]
+compiler = gcc*
+drop = -plugin *
+drop = -plugin-opt=*
+
+[hash-style]
+target = x86_64-*
+replace = --hash-style=* => --hash-style=both
+
+[gc]
+output = *.so
+append = --gc-sections "-z now"
+"#;
+
+    fn args(line: &str) -> Vec<OsString> {
+        crate::shell::split(line.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn parse_rules() {
+        let rules = Rules::parse(CONFIG).unwrap();
+        assert_eq!(
+            Rule {
+                name: "hash-style".into(),
+                target: Some("x86_64-*".into()),
+                actions: vec![Action::Replace(
+                    vec!["--hash-style=*".into()],
+                    vec!["--hash-style=both".into()]
+                )],
+                ..Default::default()
+            },
+            rules.0[1]
+        );
+        assert_eq!(
+            Action::Append(vec!["--gc-sections".into(), "-z now".into()]),
+            rules.0[2].actions[0]
+        );
+
+        assert!(Rules::parse("drop = -s").is_err());
+        assert!(Rules::parse("[a]\nremove = -s").is_err());
+        assert!(Rules::parse("[a]\nreplace = -s").is_err());
+        assert!(Rules::parse("[a]\ndrop =").is_err());
+    }
+
+    #[test]
+    fn apply_rules() {
+        let rules = Rules::parse(CONFIG).unwrap();
+        let link = args(
+            "-plugin /usr/lib/liblto_plugin.so -plugin-opt=-pass-through=-lc --hash-style=gnu \
+             -o libfoo.so foo.o",
+        );
+        let scope = Scope {
+            target: Some("x86_64-linux-gnu"),
+            output: Some(Path::new("build/libfoo.so")),
+            compiler: Path::new("/usr/bin/gcc-12"),
+        };
+        assert_eq!(
+            (
+                args("--hash-style=both -o libfoo.so foo.o --gc-sections '-z now'"),
+                vec!["no-lto-plugin", "hash-style", "gc"]
+            ),
+            rules.apply(link.clone(), &scope)
+        );
+
+        let scope = Scope {
+            target: None,
+            output: Some(Path::new("hello")),
+            compiler: Path::new("/usr/bin/clang"),
+        };
+        assert_eq!((link.clone(), vec![]), rules.apply(link, &scope));
+    }
+
+    #[test]
+    fn globs() {
+        assert!(glob("*", ""));
+        assert!(glob("-plugin-opt=*", "-plugin-opt=-fresolution=/tmp/a.res"));
+        assert!(glob("x86_64-*-linux-?nu", "x86_64-pc-linux-gnu"));
+        assert!(glob("*.so*", "libfoo.so.1"));
+        assert!(!glob("*.so", "libfoo.so.1"));
+        assert!(!glob("-plugin", "-plugin-opt=x"));
+    }
+}
@@ -0,0 +1,401 @@
+//! Differential linking. With `WILD_PROXY_SHADOW=system` the system linker produces the real output
+//! and Wild links the same inputs into a side file, with `WILD_PROXY_SHADOW=wild` it's the other way
+//! round. Both outputs are then compared structurally and a report is written only if they differ.
+//! `WILD_PROXY_SHADOW_SAMPLE` limits the overhead to a percentage of links.
+
+use crate::events;
+use anyhow::{Context, Result, bail};
+use object::{
+    Object, ObjectSection, ObjectSymbol,
+    elf::{SHT_REL, SHT_RELA, SHT_RELR},
+    read::elf::{Dyn, ElfFile64, ProgramHeader, SectionHeader},
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ffi::OsString,
+    fmt::Write,
+    hash::{BuildHasher, RandomState},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+const SHADOW_ENV: &str = "WILD_PROXY_SHADOW";
+const SAMPLE_ENV: &str = "WILD_PROXY_SHADOW_SAMPLE";
+const REPORT_DIR_ENV: &str = "WILD_PROXY_SHADOW_REPORT_DIR";
+
+/// The linker that writes the real output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Primary {
+    System,
+    Wild,
+}
+
+impl Primary {
+    fn other_name(self) -> &'static str {
+        match self {
+            Primary::System => "wild",
+            Primary::Wild => "system",
+        }
+    }
+}
+
+/// Returns the primary linker if this link should be shadowed.
+pub(crate) fn from_env() -> Result<Option<Primary>> {
+    let primary = match std::env::var(SHADOW_ENV).as_deref() {
+        Err(_) | Ok("") => return Ok(None),
+        Ok("system") => Primary::System,
+        Ok("wild") => Primary::Wild,
+        Ok(other) => bail!("Invalid {SHADOW_ENV} value `{other}`, expected `system` or `wild`"),
+    };
+    let percentage = match std::env::var(SAMPLE_ENV).as_deref() {
+        Err(_) | Ok("") => 100,
+        Ok(value) => value
+            .trim_end_matches('%')
+            .parse::<u64>()
+            .with_context(|| format!("Invalid {SAMPLE_ENV} value `{value}`"))?,
+    };
+    // A randomly seeded hasher is a good enough source of randomness for sampling.
+    let sampled = RandomState::new().hash_one(std::process::id()) % 100 < percentage;
+    Ok(sampled.then_some(primary))
+}
+
+/// Links again with the linker other than `primary` into a side file next to the real output and
+/// reports how the two outputs differ. Wild links with `wild_args`, the system linker runs
+/// `link_command`. The primary link must already be done. Problems are only reported, they never
+/// fail the build.
+pub(crate) fn run_secondary(
+    primary: Primary,
+    link_command: &[OsString],
+    wild_args: &[OsString],
+    env: &[(&str, &[u8])],
+) {
+    if let Err(e) = run_secondary_inner(primary, link_command, wild_args, env) {
+        tracing::warn!("Shadow link failed: {e:?}");
+    }
+}
+
+fn run_secondary_inner(
+    primary: Primary,
+    link_command: &[OsString],
+    wild_args: &[OsString],
+    env: &[(&str, &[u8])],
+) -> Result<()> {
+    let output = output_path(link_command);
+    let mut side_output = output.clone().into_os_string();
+    side_output.push(format!(".{}-shadow", primary.other_name()));
+    let side_output = PathBuf::from(side_output);
+
+    let secondary_result = match primary {
+        Primary::System => crate::run_wild(&with_output(wild_args, &side_output)),
+        Primary::Wild => run_system_linker(&with_output(link_command, &side_output), env),
+    };
+
+    let differences = match secondary_result {
+        Ok(()) => compare_files(&output, &side_output, primary)?,
+        Err(e) => vec![format!("The {} link failed: {e:#}", primary.other_name())],
+    };
+    if differences.is_empty() {
+        let _ = std::fs::remove_file(&side_output);
+        return Ok(());
+    }
+
+    let report_path = report_path(&output);
+    let mut report = format!(
+        "Output: {}\nShadow output: {}\nLink command: {}\n\n",
+        output.display(),
+        side_output.display(),
+        crate::shell::join(link_command)
+    );
+    for difference in &differences {
+        let _ = writeln!(report, "- {difference}");
+    }
+    std::fs::write(&report_path, report)
+        .with_context(|| format!("Failed to write `{}`", report_path.display()))?;
+    events::record(
+        "shadow-mismatch",
+        &format!(
+            "{} differs from the shadow link, see {}",
+            output.display(),
+            report_path.display()
+        ),
+    );
+    Ok(())
+}
+
+fn run_system_linker(link_command: &[OsString], env: &[(&str, &[u8])]) -> Result<()> {
+    let linker = link_command[0].to_string_lossy();
+    let output = Command::new(&link_command[0])
+        .args(&link_command[1..])
+        .envs(crate::env_vars(env))
+        .output()
+        .with_context(|| format!("Failed to run {linker}"))?;
+    if !output.status.success() {
+        bail!(
+            "{linker} exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        );
+    }
+    Ok(())
+}
+
+/// Returns `args` writing to `output` instead.
+fn with_output(args: &[OsString], output: &Path) -> Vec<OsString> {
+    let mut args = args.to_vec();
+    match args.iter().position(|arg| arg == "-o") {
+        Some(index) if index + 1 < args.len() => {
+            args[index + 1] = output.into();
+        }
+        _ => {
+            args.push("-o".into());
+            args.push(output.into());
+        }
+    }
+    args
+}
+
+fn output_path(link_command: &[OsString]) -> PathBuf {
+    link_command
+        .windows(2)
+        .find_map(|window| (window[0] == "-o").then(|| PathBuf::from(&window[1])))
+        .unwrap_or_else(|| PathBuf::from("a.out"))
+}
+
+fn report_path(output: &Path) -> PathBuf {
+    let file_name = output
+        .file_name()
+        .map_or_else(|| "a.out".into(), |name| name.to_string_lossy());
+    match std::env::var_os(REPORT_DIR_ENV).filter(|dir| !dir.is_empty()) {
+        Some(dir) => PathBuf::from(dir).join(format!(
+            "{file_name}-{}.shadow-report.txt",
+            std::process::id()
+        )),
+        None => output.with_file_name(format!("{file_name}.shadow-report.txt")),
+    }
+}
+
+fn compare_files(output: &Path, side_output: &Path, primary: Primary) -> Result<Vec<String>> {
+    let (system, wild) = match primary {
+        Primary::System => (output, side_output),
+        Primary::Wild => (side_output, output),
+    };
+    let read = |path: &Path| {
+        std::fs::read(path).with_context(|| format!("Failed to read `{}`", path.display()))
+    };
+    let system_data = read(system)?;
+    let wild_data = read(wild)?;
+    Ok(compare(
+        &ElfSummary::new(&system_data).context("Failed to parse system linker output")?,
+        &ElfSummary::new(&wild_data).context("Failed to parse Wild output")?,
+    ))
+}
+
+/// The parts of an ELF file that should match no matter which linker produced it. Addresses are
+/// deliberately left out, since every linker lays out the file differently.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct ElfSummary {
+    /// Symbol at the entry point, or the address if there's no such symbol.
+    entry: String,
+    needed: Vec<String>,
+    /// Type and flags of every program header.
+    program_headers: Vec<(u32, u32)>,
+    sections: BTreeSet<String>,
+    /// Dynamic symbols, with undefined ones prefixed by `U `.
+    dynamic_symbols: BTreeSet<String>,
+    /// Number of relocations in each relocation section.
+    relocations: BTreeMap<String, u64>,
+}
+
+impl ElfSummary {
+    fn new(data: &[u8]) -> Result<Self> {
+        let file = ElfFile64::<object::Endianness>::parse(data)?;
+        let endian = file.endian();
+
+        let entry_address = file.entry();
+        let entry = file
+            .symbols()
+            .find(|symbol| {
+                symbol.address() == entry_address && !symbol.name().unwrap_or("").is_empty()
+            })
+            .and_then(|symbol| symbol.name().ok().map(str::to_owned))
+            .unwrap_or_else(|| format!("{entry_address:#x}"));
+
+        let mut needed = Vec::new();
+        let section_table = file.elf_section_table();
+        if let Some((entries, strings_index)) = section_table.dynamic(endian, data)? {
+            let strings = section_table.strings(endian, data, strings_index)?;
+            for entry in entries {
+                if entry.tag32(endian) == Some(object::elf::DT_NEEDED) {
+                    needed
+                        .push(String::from_utf8_lossy(entry.string(endian, strings)?).into_owned());
+                }
+            }
+        }
+
+        let mut program_headers = file
+            .elf_program_headers()
+            .iter()
+            .map(|segment| (segment.p_type(endian), segment.p_flags(endian)))
+            .collect::<Vec<_>>();
+        program_headers.sort_unstable();
+
+        let sections = file
+            .sections()
+            .filter_map(|section| section.name().ok().map(str::to_owned))
+            .filter(|name| !name.is_empty())
+            .collect();
+
+        let dynamic_symbols = file
+            .dynamic_symbols()
+            .filter_map(|symbol| {
+                let name = symbol.name().ok().filter(|name| !name.is_empty())?;
+                Some(if symbol.is_undefined() {
+                    format!("U {name}")
+                } else {
+                    name.to_owned()
+                })
+            })
+            .collect();
+
+        let mut relocations = BTreeMap::new();
+        for section in section_table.iter() {
+            if matches!(section.sh_type(endian), SHT_REL | SHT_RELA | SHT_RELR) {
+                let entry_size = section.sh_entsize(endian).max(1);
+                let name = section_table.section_name(endian, section)?;
+                relocations.insert(
+                    String::from_utf8_lossy(name).into_owned(),
+                    section.sh_size(endian) / entry_size,
+                );
+            }
+        }
+
+        Ok(Self {
+            entry,
+            needed,
+            program_headers,
+            sections,
+            dynamic_symbols,
+            relocations,
+        })
+    }
+}
+
+/// Describes every difference between the output of the system linker and Wild.
+fn compare(system: &ElfSummary, wild: &ElfSummary) -> Vec<String> {
+    let mut differences = Vec::new();
+    if system.entry != wild.entry {
+        differences.push(format!(
+            "Entry point: system `{}`, wild `{}`",
+            system.entry, wild.entry
+        ));
+    }
+    if system.needed != wild.needed {
+        differences.push(format!(
+            "NEEDED entries: system {:?}, wild {:?}",
+            system.needed, wild.needed
+        ));
+    }
+    if system.program_headers != wild.program_headers {
+        let describe = |headers: &[(u32, u32)]| {
+            headers
+                .iter()
+                .map(|(p_type, p_flags)| format!("{p_type:#x}/{p_flags:#x}"))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        differences.push(format!(
+            "Program headers (type/flags): system [{}], wild [{}]",
+            describe(&system.program_headers),
+            describe(&wild.program_headers)
+        ));
+    }
+    compare_sets(
+        "Sections",
+        &system.sections,
+        &wild.sections,
+        &mut differences,
+    );
+    compare_sets(
+        "Dynamic symbols",
+        &system.dynamic_symbols,
+        &wild.dynamic_symbols,
+        &mut differences,
+    );
+    let total = |relocations: &BTreeMap<String, u64>| relocations.values().sum::<u64>();
+    if total(&system.relocations) != total(&wild.relocations) {
+        differences.push(format!(
+            "Relocation counts: system {:?}, wild {:?}",
+            system.relocations, wild.relocations
+        ));
+    }
+    differences
+}
+
+fn compare_sets(
+    what: &str,
+    system: &BTreeSet<String>,
+    wild: &BTreeSet<String>,
+    differences: &mut Vec<String>,
+) {
+    let only_system = system.difference(wild).collect::<Vec<_>>();
+    let only_wild = wild.difference(system).collect::<Vec<_>>();
+    if !only_system.is_empty() {
+        differences.push(format!("{what} only in system output: {only_system:?}"));
+    }
+    if !only_wild.is_empty() {
+        differences.push(format!("{what} only in wild output: {only_wild:?}"));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn summary() -> ElfSummary {
+        ElfSummary {
+            entry: "_start".to_owned(),
+            needed: vec!["libc.so.6".to_owned()],
+            program_headers: vec![(1, 4), (1, 5)],
+            sections: [".text", ".data"].map(str::to_owned).into(),
+            dynamic_symbols: ["U puts"].map(str::to_owned).into(),
+            relocations: [(".rela.dyn".to_owned(), 8)].into(),
+        }
+    }
+
+    #[test]
+    fn identical_outputs() {
+        assert!(compare(&summary(), &summary()).is_empty());
+    }
+
+    #[test]
+    fn relocations_are_compared_in_total() {
+        let mut wild = summary();
+        wild.relocations = [(".rela.dyn".to_owned(), 5), (".rela.plt".to_owned(), 3)].into();
+        assert!(compare(&summary(), &wild).is_empty());
+    }
+
+    #[test]
+    fn differing_outputs() {
+        let mut wild = summary();
+        wild.entry = "0x1000".to_owned();
+        wild.needed.clear();
+        wild.sections.insert(".wild".to_owned());
+        assert_eq!(
+            vec![
+                "Entry point: system `_start`, wild `0x1000`",
+                r#"NEEDED entries: system ["libc.so.6"], wild []"#,
+                r#"Sections only in wild output: [".wild"]"#,
+            ],
+            compare(&summary(), &wild)
+        );
+    }
+
+    #[test]
+    fn summarize_own_binary() {
+        let data = std::fs::read(std::env::current_exe().unwrap()).unwrap();
+        let summary = ElfSummary::new(&data).unwrap();
+        assert!(summary.sections.contains(".text"));
+        assert!(!summary.program_headers.is_empty());
+    }
+}
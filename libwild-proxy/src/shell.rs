@@ -0,0 +1,110 @@
+//! Splitting the commands from the compiler dump into arguments. `shell_words` only works on
+//! `str`, but paths in the dump are whatever bytes the file system has, e.g. Latin-1 file names.
+
+use anyhow::{Result, bail};
+use std::{
+    ffi::{OsStr, OsString},
+    os::unix::ffi::OsStrExt,
+};
+
+/// Splits `line` into arguments following the POSIX shell quoting rules, like
+/// `shell_words::split`.
+pub(crate) fn split(line: &[u8]) -> Result<Vec<OsString>> {
+    let mut args = Vec::new();
+    let mut arg = Vec::new();
+    let mut in_arg = false;
+    let mut bytes = line.iter().copied();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b' ' | b'\t' | b'\n' => {
+                if in_arg {
+                    args.push(OsStr::from_bytes(&arg).to_owned());
+                    arg.clear();
+                    in_arg = false;
+                }
+                continue;
+            }
+            b'\'' => loop {
+                match bytes.next() {
+                    Some(b'\'') => break,
+                    Some(byte) => arg.push(byte),
+                    None => bail!("Missing closing quote in `{}`", line.escape_ascii()),
+                }
+            },
+            b'"' => loop {
+                match bytes.next() {
+                    Some(b'"') => break,
+                    Some(b'\\') => match bytes.next() {
+                        Some(b'\n') => {}
+                        Some(byte @ (b'$' | b'`' | b'"' | b'\\')) => arg.push(byte),
+                        Some(byte) => arg.extend([b'\\', byte]),
+                        None => bail!("Missing closing quote in `{}`", line.escape_ascii()),
+                    },
+                    Some(byte) => arg.push(byte),
+                    None => bail!("Missing closing quote in `{}`", line.escape_ascii()),
+                }
+            },
+            b'\\' => match bytes.next() {
+                Some(b'\n') => {}
+                Some(byte) => arg.push(byte),
+                None => bail!("Trailing backslash in `{}`", line.escape_ascii()),
+            },
+            byte => arg.push(byte),
+        }
+        in_arg = true;
+    }
+    if in_arg {
+        args.push(OsStr::from_bytes(&arg).to_owned());
+    }
+    Ok(args)
+}
+
+/// Joins arguments into a command line for display. Arguments that aren't UTF-8 are shown lossily.
+pub(crate) fn join(args: &[OsString]) -> String {
+    shell_words::join(args.iter().map(|arg| arg.to_string_lossy()))
+}
+
+/// Arguments as strings, for outputs that can only hold UTF-8 like JSON.
+pub(crate) fn lossy(args: &[OsString]) -> Vec<String> {
+    args.iter()
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn same_as_shell_words() {
+        for line in [
+            r#""/usr/bin/ld" "-o" "a.out" "hello.o""#,
+            r#" /usr/lib/gcc/x86_64-linux-gnu/12/cc1 -quiet hello.c -o /tmp/ccXyZ.s"#,
+            r#"collect2 '-plugin-opt=-pass-through=-lc' "-DX=\"quoted\"" a\ b"#,
+            r#"'' "" "\a\$\`\\" 'don'\''t'"#,
+        ] {
+            assert_eq!(
+                shell_words::split(line).unwrap(),
+                split(line.as_bytes())
+                    .unwrap()
+                    .into_iter()
+                    .map(|arg| arg.into_string().unwrap())
+                    .collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[test]
+    fn non_utf8_arguments() {
+        assert_eq!(
+            vec![
+                OsString::from("-o"),
+                OsStr::from_bytes(b"caf\xe9").to_owned(),
+                OsStr::from_bytes(b"/tmp/\xff \xfe.o").to_owned(),
+            ],
+            split(b"-o caf\xe9 \"/tmp/\xff \xfe.o\"").unwrap()
+        );
+        assert!(split(b"\"caf\xe9").is_err());
+    }
+}
@@ -0,0 +1,221 @@
+//! libwild only takes UTF-8 arguments. Files whose paths aren't UTF-8 are passed through symlinks
+//! with UTF-8 names in a temporary directory instead, and outputs are written under a UTF-8 name
+//! next to the real output and renamed into place after the link.
+
+use anyhow::{Context, Result, bail};
+use std::{
+    ffi::{OsStr, OsString},
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Options taking a file Wild reads, in their joined forms. The separate forms like `-L <dir>` are
+/// covered by the bare path.
+const INPUT_PREFIXES: &[&str] = &[
+    "",
+    "-L",
+    "-T",
+    "--library-path=",
+    "--script=",
+    "--version-script=",
+    "--dynamic-list=",
+];
+
+const OUTPUT_PREFIXES: &[&str] = &["-o", "--output="];
+
+/// Options whose value ends up in the output, so it can't be swapped for an alias.
+const EMBEDDED_VALUE_OPTIONS: &[&str] = &[
+    "-rpath",
+    "-R",
+    "-soname",
+    "-h",
+    "-dynamic-linker",
+    "--dynamic-linker",
+];
+
+/// Tells apart the alias directories of links in the same process.
+static ALIAS_DIRS: AtomicUsize = AtomicUsize::new(0);
+
+pub(crate) struct WildArgs {
+    pub(crate) args: Vec<String>,
+    /// Created on the first alias.
+    alias_dir: Option<PathBuf>,
+    aliases: usize,
+    /// Outputs written under a UTF-8 name, with their real paths.
+    renames: Vec<(PathBuf, PathBuf)>,
+}
+
+impl WildArgs {
+    pub(crate) fn new(args: &[OsString]) -> Result<Self> {
+        let mut wild_args = Self {
+            args: Vec::with_capacity(args.len()),
+            alias_dir: None,
+            aliases: 0,
+            renames: Vec::new(),
+        };
+        let mut previous = "";
+        for arg in args {
+            let wild_arg = match arg.to_str() {
+                Some(arg) => arg.to_owned(),
+                None if matches!(previous, "-o" | "--output") => {
+                    wild_args.alias_output(Path::new(arg))?
+                }
+                None if !EMBEDDED_VALUE_OPTIONS.contains(&previous) => wild_args.alias(arg)?,
+                None => bail!(
+                    "Wild only takes UTF-8 arguments, `{previous} {}` can't be passed to it",
+                    arg.to_string_lossy()
+                ),
+            };
+            wild_args.args.push(wild_arg);
+            previous = arg.to_str().unwrap_or_default();
+        }
+        Ok(wild_args)
+    }
+
+    /// Moves the outputs to their real paths, once the link succeeded.
+    pub(crate) fn finish(&mut self) -> Result<()> {
+        for (written, output) in std::mem::take(&mut self.renames) {
+            std::fs::rename(&written, &output).with_context(|| {
+                format!(
+                    "Failed to move `{}` to `{}`",
+                    written.display(),
+                    output.display()
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    fn alias(&mut self, arg: &OsStr) -> Result<String> {
+        let bytes = arg.as_bytes();
+        for prefix in OUTPUT_PREFIXES {
+            if let Some(output) = bytes.strip_prefix(prefix.as_bytes()) {
+                let output = self.alias_output(Path::new(OsStr::from_bytes(output)))?;
+                return Ok(format!("{prefix}{output}"));
+            }
+        }
+        for prefix in INPUT_PREFIXES {
+            if let Some(path) = bytes.strip_prefix(prefix.as_bytes())
+                && let path = Path::new(OsStr::from_bytes(path))
+                && path.exists()
+            {
+                return Ok(format!("{prefix}{}", self.alias_input(path)?));
+            }
+        }
+        bail!(
+            "Wild only takes UTF-8 arguments, `{}` can't be passed to it",
+            arg.to_string_lossy()
+        )
+    }
+
+    /// Returns a symlink with a UTF-8 name pointing to `path`.
+    fn alias_input(&mut self, path: &Path) -> Result<String> {
+        let dir = match &self.alias_dir {
+            Some(dir) => dir.clone(),
+            None => {
+                let dir = std::env::temp_dir().join(format!(
+                    "wild-proxy-{}-aliases-{}",
+                    std::process::id(),
+                    ALIAS_DIRS.fetch_add(1, Ordering::Relaxed)
+                ));
+                // Left behind by an earlier process with the same ID.
+                let _ = std::fs::remove_dir_all(&dir);
+                std::fs::create_dir(&dir)
+                    .with_context(|| format!("Failed to create `{}`", dir.display()))?;
+                self.alias_dir.insert(dir).clone()
+            }
+        };
+        let name = path
+            .file_name()
+            .map_or_else(|| "dir".into(), OsStr::to_string_lossy);
+        let alias = dir.join(format!("{}-{name}", self.aliases));
+        self.aliases += 1;
+        let target = std::path::absolute(path)?;
+        std::os::unix::fs::symlink(&target, &alias)
+            .with_context(|| format!("Failed to create `{}`", alias.display()))?;
+        alias
+            .into_os_string()
+            .into_string()
+            .map_err(|dir| anyhow::anyhow!("Temporary directory `{}` isn't UTF-8", dir.display()))
+    }
+
+    /// Returns a UTF-8 path in the directory of `output` to write it to.
+    fn alias_output(&mut self, output: &Path) -> Result<String> {
+        let dir = output
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        let dir_alias = self.alias_input(dir)?;
+        let written_name = format!(".wild-proxy-{}-{}", std::process::id(), self.aliases);
+        self.renames.push((
+            std::path::absolute(dir)?.join(&written_name),
+            output.to_owned(),
+        ));
+        Ok(format!("{dir_alias}/{written_name}"))
+    }
+}
+
+impl Drop for WildArgs {
+    fn drop(&mut self) {
+        // Outputs of a failed link.
+        for (written, _) in &self.renames {
+            let _ = std::fs::remove_file(written);
+        }
+        if let Some(dir) = &self.alias_dir
+            && let Err(e) = std::fs::remove_dir_all(dir)
+        {
+            tracing::warn!("Failed to delete `{}`: {e}", dir.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn non_utf8_paths() {
+        let dir = std::env::temp_dir().join(format!("wild-proxy-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join(OsStr::from_bytes(b"caf\xe9.o"));
+        std::fs::write(&input, "object").unwrap();
+        let output = dir.join(OsStr::from_bytes(b"caf\xe9"));
+        let mut script = OsString::from("--script=");
+        script.push(&input);
+
+        let args = [
+            OsString::from("--as-needed"),
+            input.clone().into(),
+            script,
+            "-o".into(),
+            output.clone().into(),
+        ];
+        let mut wild_args = WildArgs::new(&args).unwrap();
+        assert_eq!("--as-needed", wild_args.args[0]);
+        assert_eq!(
+            "object",
+            std::fs::read_to_string(&wild_args.args[1]).unwrap()
+        );
+        assert_eq!(
+            "object",
+            std::fs::read_to_string(wild_args.args[2].strip_prefix("--script=").unwrap()).unwrap()
+        );
+        assert_eq!("-o", wild_args.args[3]);
+        std::fs::write(&wild_args.args[4], "linked").unwrap();
+        wild_args.finish().unwrap();
+        assert_eq!("linked", std::fs::read_to_string(&output).unwrap());
+
+        let alias_dir = wild_args.alias_dir.clone().unwrap();
+        drop(wild_args);
+        assert!(!alias_dir.exists());
+        assert!(input.exists());
+
+        // Only paths can be aliased, other values like `-rpath` ones end up in the output.
+        let rpath = [OsString::from("-rpath"), input.clone().into()];
+        assert!(WildArgs::new(&rpath).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,7 +1,33 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
+use std::path::Path;
 
 fn main() -> Result<()> {
     // libwild does that right now but probably should not
     // tracing_subscriber::fmt::init();
+    if libwild_proxy::is_invoked_as_ld() {
+        return libwild_proxy::ld();
+    }
+    let args = std::env::args_os().collect::<Vec<_>>();
+    if libwild_proxy::is_invoked_directly() {
+        let operand = |usage: &str| {
+            args.get(2)
+                .map(Path::new)
+                .context(format!("Usage: {usage}"))
+        };
+        match args.get(1).and_then(|arg| arg.to_str()) {
+            Some("replay") => {
+                return libwild_proxy::replay(operand("wild-proxy replay <bundle>")?);
+            }
+            Some("install") => {
+                return libwild_proxy::install(operand("wild-proxy install <dir>")?);
+            }
+            Some("doctor" | "explain") => return libwild_proxy::doctor(&args[2..]),
+            Some("serve") => return libwild_proxy::serve(),
+            Some("uninstall") => {
+                return libwild_proxy::uninstall(operand("wild-proxy uninstall <dir>")?);
+            }
+            _ => {}
+        }
+    }
     libwild_proxy::fallback()
 }
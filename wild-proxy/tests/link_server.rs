@@ -0,0 +1,77 @@
+//! Links through `wild-proxy serve`, the way a `make -j` recipe would.
+
+use std::{
+    process::{Child, Command, Stdio},
+    time::{Duration, Instant},
+};
+
+const PROXY: &str = env!("CARGO_BIN_EXE_wild-proxy");
+
+/// Stops the server however the test ends.
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn run(command: &mut Command) -> std::process::Output {
+    let output = command.output().unwrap();
+    assert!(
+        output.status.success(),
+        "{command:?} failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+#[test]
+fn link_through_server() {
+    let dir = std::env::temp_dir().join(format!("wild-proxy-server-e2e-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    // The server creates the directory of its socket, private to the user.
+    let socket = dir.join("run/link.sock");
+    std::fs::write(dir.join("main.c"), "int main(void) { return 42; }\n").unwrap();
+    run(Command::new("gcc")
+        .args(["-c", "main.c", "-o", "main.o"])
+        .current_dir(&dir));
+    run(Command::new("mkfifo").arg("jobserver").current_dir(&dir));
+
+    let _server = Server(
+        Command::new(PROXY)
+            .arg("serve")
+            .env("WILD_PROXY_SERVER_SOCKET", &socket)
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+    let started = Instant::now();
+    while !socket.exists() {
+        assert!(started.elapsed() < Duration::from_secs(10), "No server");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    // Like make, with the jobserver as inherited descriptors and a token in it.
+    let output = run(Command::new("sh")
+        .arg("-c")
+        .arg(r#"exec 3<>jobserver; printf + >&3; exec "$@""#)
+        .args(["sh", PROXY, "gcc", "-v", "main.o", "-o", "main"])
+        .env("WILD_PROXY_SERVER_SOCKET", &socket)
+        .env("MAKEFLAGS", "-j2 --jobserver-auth=3,3")
+        .current_dir(&dir));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(&format!(
+            "linked by the link server at {}",
+            socket.display()
+        )),
+        "{stderr}"
+    );
+    let status = Command::new(dir.join("main")).status().unwrap();
+    assert_eq!(Some(42), status.code());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}